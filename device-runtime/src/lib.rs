@@ -0,0 +1,190 @@
+//! The host side of a launch behind one trait, so that the examples run on a
+//! CUDA device or, through `simt-emulator`, on machines without one.
+
+#![feature(abi_ptx)]
+
+use cuda_tools::cuda_slice::CUDASlice;
+use cuda_tools::runtime::Runtime;
+use std::alloc::{alloc, dealloc, Layout};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::ptr;
+
+#[derive(Debug)]
+pub struct RuntimeError(pub String);
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+fn runtime_error<E: fmt::Debug>(e: E) -> RuntimeError {
+    RuntimeError(format!("{:?}", e))
+}
+
+/// The part of `cuda_tools::runtime::Runtime` the examples use: allocating
+/// argument buffers, launching a kernel over a grid and reading buffers back.
+pub trait DeviceRuntime {
+    fn record_function_name<A>(&mut self, kernel: extern "ptx-kernel" fn(&A), name: &str);
+
+    fn alloc_slice<'a, T>(&'a self, xs: &[T]) -> Result<CUDASlice<'a, T>, RuntimeError>;
+
+    fn launch<A>(
+        &self,
+        kernel: extern "ptx-kernel" fn(&A),
+        args: &A,
+        grid_dim_x: usize,
+        grid_dim_y: usize,
+        grid_dim_z: usize,
+        block_dim_x: usize,
+        block_dim_y: usize,
+        block_dim_z: usize,
+    ) -> Result<(), RuntimeError>;
+
+    fn to_host<T>(&self, slice: &CUDASlice<T>) -> Result<Vec<T>, RuntimeError>;
+}
+
+impl DeviceRuntime for Runtime {
+    fn record_function_name<A>(&mut self, kernel: extern "ptx-kernel" fn(&A), name: &str) {
+        Runtime::record_function_name(self, kernel, name);
+    }
+
+    fn alloc_slice<'a, T>(&'a self, xs: &[T]) -> Result<CUDASlice<'a, T>, RuntimeError> {
+        Runtime::alloc_slice(self, xs).map_err(runtime_error)
+    }
+
+    fn launch<A>(
+        &self,
+        kernel: extern "ptx-kernel" fn(&A),
+        args: &A,
+        grid_dim_x: usize,
+        grid_dim_y: usize,
+        grid_dim_z: usize,
+        block_dim_x: usize,
+        block_dim_y: usize,
+        block_dim_z: usize,
+    ) -> Result<(), RuntimeError> {
+        Runtime::launch(
+            self,
+            kernel,
+            args,
+            grid_dim_x,
+            grid_dim_y,
+            grid_dim_z,
+            block_dim_x,
+            block_dim_y,
+            block_dim_z,
+        )
+        .map_err(runtime_error)
+    }
+
+    fn to_host<T>(&self, slice: &CUDASlice<T>) -> Result<Vec<T>, RuntimeError> {
+        slice.to_host().map_err(runtime_error)
+    }
+}
+
+/// In-process implementation of `DeviceRuntime`. Buffers live in host memory
+/// owned by the runtime and kernels run on `simt-emulator`.
+pub struct HostRuntime {
+    function_names: HashMap<usize, String>,
+    allocations: RefCell<Vec<(*mut u8, Layout)>>,
+}
+
+impl HostRuntime {
+    pub fn new() -> HostRuntime {
+        HostRuntime {
+            function_names: HashMap::new(),
+            allocations: RefCell::new(vec![]),
+        }
+    }
+}
+
+impl Default for HostRuntime {
+    fn default() -> HostRuntime {
+        HostRuntime::new()
+    }
+}
+
+impl Drop for HostRuntime {
+    fn drop(&mut self) {
+        for &(p, layout) in self.allocations.borrow().iter() {
+            unsafe {
+                dealloc(p, layout);
+            }
+        }
+    }
+}
+
+impl DeviceRuntime for HostRuntime {
+    fn record_function_name<A>(&mut self, kernel: extern "ptx-kernel" fn(&A), name: &str) {
+        self.function_names.insert(kernel as usize, name.to_string());
+    }
+
+    fn alloc_slice<'a, T>(&'a self, xs: &[T]) -> Result<CUDASlice<'a, T>, RuntimeError> {
+        let layout = Layout::array::<T>(xs.len()).map_err(runtime_error)?;
+        let p = if layout.size() == 0 {
+            ptr::NonNull::<T>::dangling().as_ptr()
+        } else {
+            let p = unsafe { alloc(layout) };
+            if p.is_null() {
+                return Err(RuntimeError(format!(
+                    "failed to allocate {} bytes",
+                    layout.size()
+                )));
+            }
+            self.allocations.borrow_mut().push((p, layout));
+            p as *mut T
+        };
+        unsafe {
+            ptr::copy_nonoverlapping(xs.as_ptr(), p, xs.len());
+            Ok(CUDASlice::from_raw_parts(p, xs.len()))
+        }
+    }
+
+    fn launch<A>(
+        &self,
+        kernel: extern "ptx-kernel" fn(&A),
+        args: &A,
+        grid_dim_x: usize,
+        grid_dim_y: usize,
+        grid_dim_z: usize,
+        block_dim_x: usize,
+        block_dim_y: usize,
+        block_dim_z: usize,
+    ) -> Result<(), RuntimeError> {
+        if !self.function_names.contains_key(&(kernel as usize)) {
+            return Err(RuntimeError(
+                "launched a function that was not recorded".to_string(),
+            ));
+        }
+        unsafe {
+            simt_emulator::launch(
+                kernel,
+                args,
+                grid_dim_x,
+                grid_dim_y,
+                grid_dim_z,
+                block_dim_x,
+                block_dim_y,
+                block_dim_z,
+            );
+        }
+        Ok(())
+    }
+
+    fn to_host<T>(&self, slice: &CUDASlice<T>) -> Result<Vec<T>, RuntimeError> {
+        let len = slice.len();
+        let mut res = Vec::with_capacity(len);
+        unsafe {
+            if len != 0 {
+                ptr::copy_nonoverlapping(&slice[0] as *const T, res.as_mut_ptr(), len);
+            }
+            res.set_len(len);
+        }
+        Ok(res)
+    }
+}
@@ -0,0 +1,70 @@
+use crate::vec3::*;
+use crate::ray::*;
+
+#[repr(C)]
+#[derive(Clone,Copy)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// The box containing nothing, the identity of `surrounding`.
+    #[inline(always)]
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vec3{x: core::f32::INFINITY, y: core::f32::INFINITY, z: core::f32::INFINITY},
+            max: Vec3{x: core::f32::NEG_INFINITY, y: core::f32::NEG_INFINITY, z: core::f32::NEG_INFINITY},
+        }
+    }
+
+    #[inline(always)]
+    pub fn surrounding(a: Aabb, b: Aabb) -> Aabb {
+        Aabb {
+            min: Vec3{x: a.min.x.min(b.min.x), y: a.min.y.min(b.min.y), z: a.min.z.min(b.min.z)},
+            max: Vec3{x: a.max.x.max(b.max.x), y: a.max.y.max(b.max.y), z: a.max.z.max(b.max.z)},
+        }
+    }
+
+    /// False for the bounds of unbounded shapes such as planes, which are
+    /// kept out of the BVH.
+    #[inline(always)]
+    pub fn is_bounded(&self) -> bool {
+        let d = self.max - self.min;
+        d.x < core::f32::INFINITY && d.y < core::f32::INFINITY && d.z < core::f32::INFINITY
+    }
+
+    #[inline(always)]
+    pub fn centroid(&self) -> Vec3 {
+        0.5 * (self.min + self.max)
+    }
+
+    #[inline(always)]
+    pub fn surface_area(&self) -> f32 {
+        let d = self.max - self.min;
+        if d.x < 0.0 || d.y < 0.0 || d.z < 0.0 {
+            return 0.0;
+        }
+        2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    #[inline(always)]
+    pub fn hit(&self, ray: Ray, t_min: f32, t_max: f32) -> bool {
+        let mut t_min = t_min;
+        let mut t_max = t_max;
+        for a in 0..3 {
+            let inv_d = 1.0 / ray.direction().i(a);
+            let mut t0 = (self.min.i(a) - ray.origin().i(a)) * inv_d;
+            let mut t1 = (self.max.i(a) - ray.origin().i(a)) * inv_d;
+            if inv_d < 0.0 {
+                core::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max < t_min {
+                return false;
+            }
+        }
+        true
+    }
+}
@@ -0,0 +1,10 @@
+//! Thread indices and atomics: the real intrinsics on the device, the
+//! `simt-emulator` stand-ins everywhere else.
+
+#[cfg(target_arch = "nvptx64")]
+pub use core::arch::nvptx::*;
+#[cfg(target_arch = "nvptx64")]
+pub use core::intrinsics::atomic_cxchg;
+
+#[cfg(not(target_arch = "nvptx64"))]
+pub use simt_emulator::*;
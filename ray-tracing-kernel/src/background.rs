@@ -0,0 +1,50 @@
+use crate::vec3::*;
+use crate::math::*;
+use crate::texture::*;
+
+/// What a ray that leaves the scene sees.
+#[derive(Clone,Copy)]
+pub enum Background {
+    Constant {
+        color: Vec3,
+    },
+    /// Blends from `bottom` to `top` with the height of the ray direction.
+    Gradient {
+        bottom: Vec3,
+        top: Vec3,
+    },
+    /// Equirectangular map of `width * height` texels, top row looking up
+    /// (+y), rotated by `rotation` radians about the y axis.
+    EnvironmentMap {
+        width: usize,
+        height: usize,
+        rotation: f32,
+        intensity: f32,
+    },
+}
+
+impl Background {
+    #[inline(always)]
+    pub fn color(&self, texels: &[Vec3], direction: Vec3) -> Vec3 {
+        match *self {
+            Background::Constant{color} => color,
+            Background::Gradient{bottom,top} => {
+                let unit_direction = Vec3::unit_vector(direction);
+                let t = 0.5 * (unit_direction.y + 1.0);
+                (1.0 - t) * bottom + t * top
+            }
+            Background::EnvironmentMap{width,height,rotation,intensity} => {
+                if width == 0 || height == 0 || texels.len() < width * height {
+                    return Vec3::new();
+                }
+                let d = Vec3::unit_vector(direction);
+                let phi = atan2(d.z, d.x) + rotation;
+                let theta = acos(d.y);
+                let u = phi / (2.0 * PI);
+                let u = u - floor(u);
+                let v = theta / PI;
+                intensity * bilinear(texels, width, height, u, v)
+            }
+        }
+    }
+}
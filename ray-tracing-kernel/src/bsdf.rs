@@ -0,0 +1,418 @@
+use crate::vec3::*;
+use crate::math::*;
+use crate::onb::*;
+use crate::xorshift::*;
+use crate::hit_record::*;
+use crate::object::*;
+use crate::microfacet::*;
+use crate::spectrum::*;
+
+/// A material at a surface point, its textures looked up. All directions are
+/// unit vectors in world space pointing away from the point: `wo` towards
+/// where the light goes, `wi` towards where it comes from.
+#[derive(Clone,Copy)]
+pub struct Bsdf {
+    material: ObjectMaterial,
+    /// Shading normal, after normal mapping.
+    normal: Vec3,
+    geometric_normal: Vec3,
+    /// Albedo of `Lambertian` or base color of `Principled`.
+    albedo: Vec3,
+}
+
+/// A direction drawn by `Bsdf::sample`.
+#[derive(Clone,Copy)]
+pub struct BsdfSample {
+    pub direction: Vec3,
+    /// BSDF times cosine over the density.
+    pub weight: Vec3,
+    /// Solid angle density, `None` for materials that `eval` and `pdf`
+    /// cannot describe, such as mirrors.
+    pub pdf: Option<f32>,
+}
+
+#[inline(always)]
+fn schlick(cosine: f32, ref_idx: f32) -> f32 {
+    let r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
+    let r0 = r0 * r0;
+    let t = 1.0 - cosine;
+    r0 + (1.0 - r0) * t * t * t * t * t
+}
+
+/// Whether `wo` and `wi` lie on the same side of the surface with normal
+/// `normal`, that is whether light going from one to the other is reflected.
+#[inline(always)]
+fn same_side(normal: Vec3, wo: Vec3, wi: Vec3) -> bool {
+    Vec3::dot(wo, normal) * Vec3::dot(wi, normal) > 0.0
+}
+
+/// `normal` turned towards `wo`, for materials reflecting on either side.
+#[inline(always)]
+fn facing_normal(normal: Vec3, wo: Vec3) -> Vec3 {
+    if Vec3::dot(wo, normal) < 0.0 { -normal } else { normal }
+}
+
+/// Frame around `facing_normal(normal, wo)`.
+#[inline(always)]
+fn facing_frame(normal: Vec3, wo: Vec3) -> Onb {
+    Onb::from_w(facing_normal(normal, wo))
+}
+
+impl Bsdf {
+    /// `texels` is the shared buffer image textures index, `wo` the direction
+    /// towards where the light goes and `lambda` the wavelength traced in
+    /// spectral rendering.
+    #[inline(always)]
+    pub fn new(material: ObjectMaterial, texels: &[Vec3], rec: &HitRecord, wo: Vec3, lambda: Option<f32>) -> Bsdf {
+        let material = match material {
+            ObjectMaterial::DispersiveDielectric{ior} => {
+                ObjectMaterial::Dielectric{ref_idx: ior.at(lambda.unwrap_or(LAMBDA_D))}
+            }
+            material => material,
+        };
+        let albedo = match material {
+            ObjectMaterial::Lambertian{albedo,..} => albedo.value(texels, rec.u, rec.v, rec.p),
+            ObjectMaterial::Principled(principled) => principled.base_color.value(texels, rec.u, rec.v, rec.p),
+            _ => Vec3::new(),
+        };
+        let geometric_normal = Vec3::unit_vector(rec.geometric_normal);
+        let normal = material.normal_map().apply(texels, rec);
+        // A mapped normal that puts `wo` on the other side than the geometric
+        // normal does would shade a side of the surface not seen from there.
+        let seen_alike = Vec3::dot(wo, normal) * Vec3::dot(wo, geometric_normal) > 0.0;
+        let normal = if seen_alike { normal } else { Vec3::unit_vector(rec.normal) };
+        Bsdf {
+            material,
+            normal,
+            geometric_normal,
+            albedo,
+        }
+    }
+
+    /// Whether the shading and geometric normals agree on reflecting or
+    /// transmitting from `wo` to `wi`. Where they disagree, shading normals
+    /// would let light leak through the surface or reflect off its back, so
+    /// such directions get no contribution.
+    #[inline(always)]
+    fn consistent(&self, wo: Vec3, wi: Vec3) -> bool {
+        match self.material {
+            ObjectMaterial::Isotropic{..} => true,
+            _ => same_side(self.normal, wo, wi) == same_side(self.geometric_normal, wo, wi),
+        }
+    }
+
+    /// Whether `eval` and `pdf` describe the material, so that light
+    /// sampling can find its direct lighting.
+    #[inline(always)]
+    pub fn is_evaluable(&self) -> bool {
+        match self.material {
+            ObjectMaterial::Lambertian{..} | ObjectMaterial::Conductor{..} | ObjectMaterial::RoughDielectric{..} |
+            ObjectMaterial::Principled(_) | ObjectMaterial::Isotropic{..} => true,
+            ObjectMaterial::Metal{..} | ObjectMaterial::Dielectric{..} | ObjectMaterial::DispersiveDielectric{..} |
+            ObjectMaterial::DiffuseLight{..} => false,
+        }
+    }
+
+    /// BSDF times the cosine of `wi` (the phase function for media).
+    #[inline(always)]
+    pub fn eval(&self, wo: Vec3, wi: Vec3) -> Vec3 {
+        if !self.consistent(wo, wi) {
+            return Vec3::new();
+        }
+        match self.material {
+            ObjectMaterial::Lambertian{..} => {
+                let cosine = Vec3::dot(facing_normal(self.normal, wo), wi);
+                if cosine > 0.0 { (cosine / PI) * self.albedo } else { Vec3::new() }
+            }
+            ObjectMaterial::Conductor{ior,roughness} => {
+                let onb = facing_frame(self.normal, wo);
+                let wo = onb.to_local(wo);
+                let wi = onb.to_local(wi);
+                if wo.z <= 0.0 || wi.z <= 0.0 {
+                    return Vec3::new();
+                }
+                let alpha = alpha(roughness);
+                let h = Vec3::unit_vector(wo + wi);
+                let g = ggx_d(h, alpha) * smith_g1(wo, alpha) * smith_g1(wi, alpha) / (4.0 * wo.z);
+                g * fresnel_conductor(Vec3::dot(wo, h), ior)
+            }
+            ObjectMaterial::RoughDielectric{ref_idx,roughness} => {
+                let eta = if Vec3::dot(wo, self.normal) < 0.0 { 1.0 / ref_idx } else { ref_idx };
+                let onb = facing_frame(self.normal, wo);
+                let (f, _) = rough_dielectric_eval(onb.to_local(wo), onb.to_local(wi), eta, alpha(roughness));
+                Vec3{x: f, y: f, z: f}
+            }
+            ObjectMaterial::Principled(principled) => principled.eval(self.albedo, self.normal, wo, wi),
+            ObjectMaterial::Isotropic{albedo} => albedo / (4.0 * PI),
+            ObjectMaterial::Metal{..} | ObjectMaterial::Dielectric{..} | ObjectMaterial::DispersiveDielectric{..} |
+            ObjectMaterial::DiffuseLight{..} => Vec3::new(),
+        }
+    }
+
+    /// Solid angle density `sample` draws `wi` with.
+    #[inline(always)]
+    pub fn pdf(&self, wo: Vec3, wi: Vec3) -> f32 {
+        if !self.consistent(wo, wi) {
+            return 0.0;
+        }
+        match self.material {
+            ObjectMaterial::Lambertian{..} => {
+                let cosine = Vec3::dot(facing_normal(self.normal, wo), wi);
+                if cosine > 0.0 { cosine / PI } else { 0.0 }
+            }
+            ObjectMaterial::Conductor{roughness,..} => {
+                let onb = facing_frame(self.normal, wo);
+                ggx_reflection_pdf(onb.to_local(wo), onb.to_local(wi), alpha(roughness))
+            }
+            ObjectMaterial::RoughDielectric{ref_idx,roughness} => {
+                let eta = if Vec3::dot(wo, self.normal) < 0.0 { 1.0 / ref_idx } else { ref_idx };
+                let onb = facing_frame(self.normal, wo);
+                rough_dielectric_eval(onb.to_local(wo), onb.to_local(wi), eta, alpha(roughness)).1
+            }
+            ObjectMaterial::Principled(principled) => principled.pdf(self.normal, wo, wi),
+            ObjectMaterial::Isotropic{..} => 1.0 / (4.0 * PI),
+            ObjectMaterial::Metal{..} | ObjectMaterial::Dielectric{..} | ObjectMaterial::DispersiveDielectric{..} |
+            ObjectMaterial::DiffuseLight{..} => 0.0,
+        }
+    }
+
+    #[inline(always)]
+    pub fn sample(&self, xorshift: &mut XorShift, wo: Vec3) -> Option<BsdfSample> {
+        let direction = match self.material {
+            ObjectMaterial::Lambertian{..} => {
+                facing_frame(self.normal, wo).local(random_cosine_direction(xorshift))
+            }
+            ObjectMaterial::Conductor{roughness,..} => {
+                let onb = facing_frame(self.normal, wo);
+                let wo = onb.to_local(wo);
+                let m = sample_visible_normal(wo, alpha(roughness), xorshift.gen_f32(), xorshift.gen_f32());
+                onb.local(reflect_about(wo, m))
+            }
+            ObjectMaterial::RoughDielectric{ref_idx,roughness} => {
+                sample_rough_dielectric(xorshift, self.normal, wo, ref_idx, alpha(roughness))?.1
+            }
+            ObjectMaterial::Principled(principled) => principled.sample(xorshift, self.normal, wo)?,
+            ObjectMaterial::Isotropic{..} => Vec3::unit_vector(Vec3::random_in_unit_sphere(xorshift)),
+            ObjectMaterial::Metal{albedo,fuzz} => {
+                let reflected = Vec3::reflect(-wo, self.normal);
+                let direction = reflected + fuzz * Vec3::random_in_unit_sphere(xorshift);
+                if Vec3::dot(direction, self.normal) > 0.0 && self.consistent(wo, direction) {
+                    return Some(BsdfSample{direction, weight: albedo, pdf: None});
+                }
+                return None;
+            }
+            ObjectMaterial::Dielectric{ref_idx} => {
+                let direction = -wo;
+                let reflected = Vec3::reflect(direction, self.normal);
+                let (outward_normal, ni_over_nt, cosine) = if Vec3::dot(direction, self.normal) > 0.0 {
+                    (-self.normal, ref_idx, ref_idx * Vec3::dot(direction, self.normal))
+                }
+                else {
+                    (self.normal, 1.0 / ref_idx, -Vec3::dot(direction, self.normal))
+                };
+                let direction = match Vec3::refract(direction, outward_normal, ni_over_nt) {
+                    Some(refracted) if xorshift.gen_f32() >= schlick(cosine, ref_idx) => refracted,
+                    _ => reflected,
+                };
+                if !self.consistent(wo, direction) {
+                    return None;
+                }
+                return Some(BsdfSample{direction, weight: Vec3{x: 1.0, y: 1.0, z: 1.0}, pdf: None});
+            }
+            // Resolved into `Dielectric` by `new`.
+            ObjectMaterial::DispersiveDielectric{..} | ObjectMaterial::DiffuseLight{..} => return None,
+        };
+        // Sampling from `pdf` makes the weight eval / pdf, even where a
+        // material mixes several strategies.
+        let direction = Vec3::unit_vector(direction);
+        let pdf = self.pdf(wo, direction);
+        if !(pdf > 0.0) {
+            return None;
+        }
+        Some(BsdfSample{direction, weight: self.eval(wo, direction) / pdf, pdf: Some(pdf)})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::normal_map::*;
+    use crate::principled::*;
+    use crate::texture::*;
+
+    const N_SAMPLE: usize = 1 << 20;
+    const EPSILON: f32 = 0.03;
+
+    fn rec(normal: Vec3) -> HitRecord {
+        let geometric_normal = Vec3{x: 0.0, y: 0.0, z: 1.0};
+        HitRecord{t: 1.0, p: Vec3::new(), normal, geometric_normal, tangent: Vec3{x: 1.0, y: 0.0, z: 0.0}, bitangent: Vec3{x: 0.0, y: 1.0, z: 0.0}, u: 0.5, v: 0.5, object_id: 0}
+    }
+
+    fn white() -> Texture {
+        Texture::solid(Vec3{x: 1.0, y: 1.0, z: 1.0})
+    }
+
+    /// Direction at the given cosine with the z axis, in the xz plane.
+    fn at_cosine(cosine: f32) -> Vec3 {
+        Vec3{x: sqrt(1.0 - cosine * cosine), y: 0.0, z: cosine}
+    }
+
+    fn uniform_sphere(xorshift: &mut XorShift) -> Vec3 {
+        let z = 1.0 - 2.0 * xorshift.gen_f32();
+        let r = sqrt((1.0 - z * z).max(0.0));
+        let phi = 2.0 * PI * xorshift.gen_f32();
+        Vec3{x: r * cos(phi), y: r * sin(phi), z}
+    }
+
+    /// Sum in double precision: adding a million samples in `f32` would
+    /// lose more than the tolerance.
+    #[derive(Default)]
+    struct Sum([f64; 3]);
+
+    impl Sum {
+        fn add(&mut self, v: Vec3) {
+            self.0[0] += v.x as f64;
+            self.0[1] += v.y as f64;
+            self.0[2] += v.z as f64;
+        }
+
+        fn mean(&self, n: f64) -> Vec3 {
+            Vec3{x: (self.0[0] / n) as f32, y: (self.0[1] / n) as f32, z: (self.0[2] / n) as f32}
+        }
+    }
+
+    fn bsdf(material: ObjectMaterial, wo: Vec3) -> Bsdf {
+        Bsdf::new(material, &[], &rec(Vec3{x: 0.0, y: 0.0, z: 1.0}), wo, None)
+    }
+
+    /// Integrates `eval` and `pdf` over the sphere: the albedo must stay
+    /// between `min_albedo` and 1 in every channel and the pdf at most 1.
+    /// Returns the albedo.
+    fn check_energy(bsdf: &Bsdf, wo: Vec3, min_albedo: f32) -> Vec3 {
+        let mut xorshift = XorShift::new(1);
+        let mut albedo = Sum::default();
+        let mut total_pdf = 0.0;
+        for _ in 0..N_SAMPLE {
+            let wi = uniform_sphere(&mut xorshift);
+            albedo.add(bsdf.eval(wo, wi));
+            total_pdf += bsdf.pdf(wo, wi) as f64;
+        }
+        let albedo = albedo.mean(N_SAMPLE as f64 / (4.0 * PI as f64));
+        let total_pdf = 4.0 * PI as f64 * total_pdf / N_SAMPLE as f64;
+        for &a in &[albedo.x, albedo.y, albedo.z] {
+            assert!(a <= 1.0 + EPSILON && a >= min_albedo - EPSILON, "albedo {} {} {}", albedo.x, albedo.y, albedo.z);
+        }
+        assert!(total_pdf <= 1.0 + EPSILON as f64, "pdf integrates to {}", total_pdf);
+        albedo
+    }
+
+    /// Averages the weights of `sample`, counting failed samples as 0, which
+    /// estimates the albedo too if the directions are really drawn from
+    /// `pdf` and no direction `eval` reflects to is left out.
+    fn check_samples(bsdf: &Bsdf, wo: Vec3, albedo: Vec3) {
+        let mut xorshift = XorShift::new(2);
+        let mut mean = Sum::default();
+        for _ in 0..N_SAMPLE {
+            if let Some(s) = bsdf.sample(&mut xorshift, wo) {
+                mean.add(s.weight);
+            }
+        }
+        let mean = mean.mean(N_SAMPLE as f64);
+        for &(m, a) in &[(mean.x, albedo.x), (mean.y, albedo.y), (mean.z, albedo.z)] {
+            assert!(abs(m - a) <= EPSILON, "mean weight {} != albedo {}", m, a);
+        }
+    }
+
+    fn check(material: ObjectMaterial, cosines: &[f32], min_albedo: f32) {
+        for &cosine in cosines {
+            let wo = at_cosine(cosine);
+            let bsdf = bsdf(material, wo);
+            let albedo = check_energy(&bsdf, wo, min_albedo);
+            check_samples(&bsdf, wo, albedo);
+        }
+    }
+
+    #[test]
+    fn lambertian_conserves_energy() {
+        check(ObjectMaterial::Lambertian{albedo: white(), normal_map: NormalMap::None}, &[0.9, 0.5, 0.2, -0.5], 1.0);
+    }
+
+    #[test]
+    fn lambertian_reflects_off_the_back() {
+        let wo = at_cosine(-0.5);
+        let bsdf = bsdf(ObjectMaterial::Lambertian{albedo: white(), normal_map: NormalMap::None}, wo);
+        let mut xorshift = XorShift::new(3);
+        for _ in 0..4096 {
+            let s = bsdf.sample(&mut xorshift, wo).unwrap();
+            assert!(s.direction.z < 0.0, "sampled {} {} {}", s.direction.x, s.direction.y, s.direction.z);
+        }
+        let through = at_cosine(0.5);
+        let f = bsdf.eval(wo, through);
+        assert!(f.x == 0.0 && f.y == 0.0 && f.z == 0.0 && bsdf.pdf(wo, through) == 0.0);
+    }
+
+    #[test]
+    fn conductor_conserves_energy() {
+        check(ObjectMaterial::Conductor{ior: ALUMINIUM, roughness: 0.5}, &[0.9, 0.5, 0.2, -0.5], 0.0);
+    }
+
+    #[test]
+    fn rough_dielectric_conserves_energy() {
+        check(ObjectMaterial::RoughDielectric{ref_idx: 1.5, roughness: 0.5}, &[0.9, 0.5, 0.2, -0.5, -0.9], 0.0);
+    }
+
+    #[test]
+    fn principled_conserves_energy() {
+        let principled = Principled {
+            base_color: white(),
+            metallic: 0.3,
+            roughness: 0.5,
+            specular: 0.5,
+            clearcoat: 1.0,
+            clearcoat_roughness: 0.5,
+            transmission: 0.0,
+            sheen: 0.5,
+            normal_map: NormalMap::None,
+        };
+        check(ObjectMaterial::Principled(principled), &[0.9, 0.5, 0.2], 0.0);
+        let glass = Principled{transmission: 1.0, metallic: 0.0, ..principled};
+        check(ObjectMaterial::Principled(glass), &[0.9, 0.5, -0.5], 0.0);
+    }
+
+    #[test]
+    fn isotropic_conserves_energy() {
+        check(ObjectMaterial::Isotropic{albedo: Vec3{x: 1.0, y: 1.0, z: 1.0}}, &[0.9, -0.5], 1.0);
+    }
+
+    /// Lambertian whose normal map tilts the shading normal 45 degrees
+    /// towards +x.
+    fn tilted() -> ObjectMaterial {
+        let map = Texture::solid(Vec3{x: 0.85, y: 0.5, z: 0.85});
+        ObjectMaterial::Lambertian{albedo: white(), normal_map: NormalMap::Normal{map, strength: 1.0}}
+    }
+
+    #[test]
+    fn normal_mapped_lambertian_conserves_energy() {
+        let wo = Vec3::unit_vector(Vec3{x: 0.3, y: 0.1, z: 0.9});
+        let bsdf = bsdf(tilted(), wo);
+        assert!(abs(bsdf.normal.x - bsdf.normal.z) < 1e-5 && bsdf.normal.x > 0.5);
+        let albedo = check_energy(&bsdf, wo, 0.0);
+        check_samples(&bsdf, wo, albedo);
+        // In front of the shading normal but behind the surface: leaking
+        // light through it is what `consistent` prevents.
+        let wi = Vec3::unit_vector(Vec3{x: 1.0, y: 0.0, z: -0.2});
+        assert!(Vec3::dot(wi, bsdf.normal) > 0.0);
+        let f = bsdf.eval(wo, wi);
+        assert!(f.x == 0.0 && f.y == 0.0 && f.z == 0.0 && bsdf.pdf(wo, wi) == 0.0);
+    }
+
+    #[test]
+    fn normal_map_facing_away_from_wo_is_ignored() {
+        // `wo` is behind the tilted normal but in front of the surface.
+        let wo = Vec3::unit_vector(Vec3{x: -1.0, y: 0.0, z: 0.2});
+        let bsdf = bsdf(tilted(), wo);
+        assert!(bsdf.normal.z == 1.0);
+        let albedo = check_energy(&bsdf, wo, 1.0);
+        check_samples(&bsdf, wo, albedo);
+    }
+}
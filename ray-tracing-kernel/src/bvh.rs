@@ -0,0 +1,27 @@
+use crate::aabb::*;
+
+/// Deepest leaf a BVH may have, counting the root as depth 0. The builder
+/// stops splitting there, which keeps the far children pending during a
+/// traversal within a stack of this size.
+pub const BVH_MAX_DEPTH: usize = 32;
+
+/// A node of a flattened BVH laid out in depth-first order: the first child of
+/// an interior node is the node right after it.
+#[repr(C)]
+#[derive(Clone,Copy)]
+pub struct BvhNode {
+    pub bounds: Aabb,
+    /// Interior node: index of the second child. Leaf: index of its first object.
+    pub offset: u32,
+    /// Number of objects in a leaf, 0 for interior nodes.
+    pub count: u32,
+    /// Axis the children of an interior node were split along.
+    pub axis: u32,
+}
+
+impl BvhNode {
+    #[inline(always)]
+    pub fn is_leaf(&self) -> bool {
+        self.count != 0
+    }
+}
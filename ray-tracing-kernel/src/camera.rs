@@ -12,6 +12,10 @@ pub struct Camera {
     pub v: Vec3,
     pub w: Vec3,
     pub lens_radius: f32,
+    /// Shutter interval. Rays are cast at uniformly distributed instants in
+    /// [time0, time1], or all at time0 when the interval is empty.
+    pub time0: f32,
+    pub time1: f32,
 }
 
 #[inline(always)]
@@ -29,6 +33,12 @@ impl Camera {
     pub fn get_ray(&self, xorshift: &mut XorShift, s: f32, t: f32) -> Ray {
         let rd = self.lens_radius * random_in_unit_disk(xorshift);
         let offset = self.u * rd.x + self.v * rd.y;
-        Ray::new_from_origin_and_direction(self.origin + offset, self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset)
+        let time = if self.time1 > self.time0 {
+            self.time0 + xorshift.gen_f32() * (self.time1 - self.time0)
+        }
+        else {
+            self.time0
+        };
+        Ray::new_from_origin_direction_and_time(self.origin + offset, self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset, time)
     }
 }
\ No newline at end of file
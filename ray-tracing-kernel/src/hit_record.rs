@@ -3,6 +3,20 @@ use crate::vec3::*;
 pub struct HitRecord {
     pub t: f32,
     pub p: Vec3,
+    /// Shading normal, interpolated for smooth triangles. Normal and bump
+    /// maps are applied later, by `Bsdf::new`.
     pub normal: Vec3,
+    /// Normal of the actual surface, on the side of `normal`.
+    pub geometric_normal: Vec3,
+    /// Directions of increasing `u` and `v` along the surface, not
+    /// normalized, zero where the surface has no such parametrization.
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+    /// Surface coordinates of `p`: longitude and latitude for spheres, the
+    /// interpolated texture coordinates for mesh triangles that have them and
+    /// barycentrics of the second and third vertex for other triangles, the
+    /// position within the rectangle for rectangles, zero otherwise.
+    pub u: f32,
+    pub v: f32,
     pub object_id: usize,
 }
@@ -1,61 +1,389 @@
-#[cfg(target_arch = "nvptx64")]
 use crate::ray::*;
-#[cfg(target_arch = "nvptx64")]
 use crate::vec3::*;
 use crate::ray_trace_args::*;
-#[cfg(target_arch = "nvptx64")]
 use crate::hit_record::*;
-#[cfg(target_arch = "nvptx64")]
+use crate::scene::*;
+use crate::bvh::*;
 use crate::xorshift::*;
+use crate::object::*;
+use crate::bsdf::*;
+use crate::medium::*;
+use crate::light::*;
+use crate::math::*;
+use crate::spectrum::*;
 
-#[cfg(target_arch = "nvptx64")]
-fn color(args: &RayTraceArgs, xorshift: &mut XorShift, ray: Ray) -> Vec3 {
+/// Radiance arriving along `ray`. In spectral rendering, where `lambda` is the
+/// wavelength traced, every component holds the spectral radiance at it.
+pub fn color(scene: &Scene, xorshift: &mut XorShift, ray: Ray, lambda: Option<f32>) -> Vec3 {
     let mut ratio = Vec3{x: 1.0, y: 1.0, z: 1.0};
+    let mut res = Vec3::new();
     let mut ray = ray;
-    for _ in 0..50 {
-        if let Some(rec) = hit(args, ray, 0.001, 1e10) {
-            if rec.object_id < args.objects.len() {
-                if let Some((attenuation, scattered)) = args.objects[rec.object_id].material.scatter(xorshift, ray, rec) {
-                    ratio *= attenuation;
-                    ray = scattered;
-                }
-                else {
-                    return Vec3{x: 0.0, y: 0.0, z: 0.0}
-                }                
+    // Density of the bounce `ray` was sampled with, `None` for camera rays
+    // and other directions light sampling could not have produced.
+    let mut bsdf_pdf = None;
+    for depth in 0..scene.path.max_depth {
+        let surface = hit(scene, ray, 0.001, 1e10);
+        let t_surface = match surface {
+            Some(ref rec) => rec.t,
+            None => core::f32::INFINITY,
+        };
+        let (rec, material) = if let Some((t, material)) = sample_media(scene, xorshift, ray, 0.001, t_surface) {
+            let p = ray.point_at_parameter(t);
+            let normal = -Vec3::unit_vector(ray.direction());
+            (HitRecord{t,p,normal,geometric_normal: normal,tangent: Vec3::new(),bitangent: Vec3::new(),u: 0.0,v: 0.0,object_id: scene.objects.len()}, material)
+        }
+        else if let Some(rec) = surface {
+            if rec.object_id >= scene.objects.len() {
+                return res;
             }
+            let material = scene.objects[rec.object_id].material;
+            res += ratio * emission_weight(scene, bsdf_pdf, ray, &rec) * project(material.emitted(), lambda);
+            (rec, material)
         }
         else {
-            let unit_direction = Vec3::unit_vector(ray.direction());
-            let t = 0.5 * (unit_direction.y + 1.0);
-            let s = (1.0 - t) * Vec3 {x: 1.0, y: 1.0, z: 1.0} + t * Vec3 {x: 0.5, y: 0.7, z: 1.0};
-            return ratio * s;
+            return res + ratio * project(scene.background.color(scene.environment, ray.direction()), lambda);
+        };
+        let wo = -Vec3::unit_vector(ray.direction());
+        let bsdf = Bsdf::new(material, scene.texels, &rec, wo, lambda);
+        if bsdf.is_evaluable() {
+            res += ratio * sample_direct(scene, xorshift, ray, &rec, &bsdf, wo, lambda);
+        }
+        let sample = match bsdf.sample(xorshift, wo) {
+            Some(sample) => sample,
+            None => return res,
+        };
+        ratio *= project(sample.weight, lambda);
+        if !scene.path.survives(depth, xorshift, &mut ratio) {
+            return res;
+        }
+        ray = Ray::new_from_origin_direction_and_time(rec.p, sample.direction, ray.time());
+        bsdf_pdf = sample.pdf;
+    }
+    res
+}
+
+/// `c` itself, or in spectral rendering the value at `lambda` of the
+/// spectrum of color `c` in every component.
+#[inline(always)]
+fn project(c: Vec3, lambda: Option<f32>) -> Vec3 {
+    match lambda {
+        Some(lambda) => {
+            let s = rgb_to_spectrum(c, lambda);
+            Vec3{x: s, y: s, z: s}
+        }
+        None => c,
+    }
+}
+
+/// Power heuristic weight of a sample drawn with density `pdf` against one
+/// drawn with density `other_pdf`.
+#[inline(always)]
+fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let a = pdf * pdf;
+    let b = other_pdf * other_pdf;
+    if a + b > 0.0 { a / (a + b) } else { 0.0 }
+}
+
+/// MIS weight of the emission found at `rec` by a bounce drawn with density
+/// `bsdf_pdf`, against light sampling having produced the same direction.
+#[inline(always)]
+fn emission_weight(scene: &Scene, bsdf_pdf: Option<f32>, ray: Ray, rec: &HitRecord) -> f32 {
+    let bsdf_pdf = match bsdf_pdf {
+        Some(pdf) => pdf,
+        None => return 1.0,
+    };
+    for light in scene.lights {
+        if light.object as usize == rec.object_id {
+            let shape = &scene.objects[rec.object_id].shape;
+            let pdf = light_pdf(shape, ray.origin(), Vec3::unit_vector(ray.direction()), rec) / scene.lights.len() as f32;
+            return power_heuristic(bsdf_pdf, pdf);
         }
     }
-    Vec3{x: 0.0, y: 0.0, z: 0.0}
+    1.0
+}
+
+/// Light scattered towards `wo` at `rec` directly from one light chosen
+/// uniformly, MIS-weighted against sampling `bsdf`.
+#[inline(always)]
+fn sample_direct(scene: &Scene, xorshift: &mut XorShift, ray: Ray, rec: &HitRecord, bsdf: &Bsdf, wo: Vec3, lambda: Option<f32>) -> Vec3 {
+    let n = scene.lights.len();
+    if n == 0 {
+        return Vec3::new();
+    }
+    let i = (xorshift.gen_f32() * n as f32) as usize;
+    let light = scene.lights[if i < n { i } else { n - 1 }];
+    if light.object as usize >= scene.objects.len() {
+        return Vec3::new();
+    }
+    let object = scene.objects[light.object as usize];
+    let sample = match sample_light(&object.shape, xorshift, rec.p) {
+        Some(sample) => sample,
+        None => return Vec3::new(),
+    };
+    let f = bsdf.eval(wo, sample.direction);
+    if !(f.x + f.y + f.z > 0.0 && sample.pdf > 0.0) {
+        return Vec3::new();
+    }
+    let shadow = Ray::new_from_origin_direction_and_time(rec.p, sample.direction, ray.time());
+    let t_light = sample.distance * (1.0 - 1e-4);
+    if hit(scene, shadow, 0.001, t_light).is_some() {
+        return Vec3::new();
+    }
+    let pdf = sample.pdf / n as f32;
+    let weight = power_heuristic(pdf, bsdf.pdf(wo, sample.direction));
+    let transmittance = transmittance(scene, shadow, 0.001, t_light);
+    (weight * transmittance / pdf) * (project(f, lambda) * project(object.material.emitted(), lambda))
 }
 
-#[cfg(target_arch = "nvptx64")]
-fn hit(args: &RayTraceArgs, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+/// Fraction of light crossing the fog and media between `t_min` and `t_max`
+/// along the ray without scattering.
+#[inline(always)]
+fn transmittance(scene: &Scene, ray: Ray, t_min: f32, t_max: f32) -> f32 {
+    let length = ray.direction().length();
+    let mut optical_depth = scene.fog.density * (t_max - t_min) * length;
+    for medium in scene.media {
+        medium_intervals(scene, medium, ray, t_min, t_max, |t0, t1| {
+            optical_depth += medium.density * (t1 - t0) * length;
+            true
+        });
+    }
+    exp(-optical_depth)
+}
+
+/// Samples free-flight distances through the global fog and every medium the
+/// ray crosses before `t_max`, returning the closest scattering event.
+#[inline(always)]
+fn sample_media(scene: &Scene, xorshift: &mut XorShift, ray: Ray, t_min: f32, t_max: f32) -> Option<(f32, ObjectMaterial)> {
     let mut res = None;
     let mut closest_so_far = t_max;
-    for i in 0..args.objects_len {
-        if i < args.objects.len() {
-            if let Some(rec) = args.objects[i].hit(i, ray, t_min, closest_so_far) {
-                closest_so_far = rec.t;
-                res = Some(rec);
+    let length = ray.direction().length();
+    if scene.fog.density > 0.0 {
+        let t = t_min + free_flight_distance(xorshift, scene.fog.density) / length;
+        if t < closest_so_far {
+            closest_so_far = t;
+            res = Some((t, ObjectMaterial::Isotropic{albedo: scene.fog.albedo}));
+        }
+    }
+    for medium in scene.media {
+        // Free flight is memoryless: sampling each interval in turn is
+        // sampling their total length.
+        let mut scatter = None;
+        medium_intervals(scene, medium, ray, t_min, closest_so_far, |t0, t1| {
+            let t = t0 + free_flight_distance(xorshift, medium.density) / length;
+            if t < t1 {
+                scatter = Some(t);
+            }
+            scatter.is_none()
+        });
+        if let Some(t) = scatter {
+            closest_so_far = t;
+            res = Some((t, medium.material));
+        }
+    }
+    res
+}
+
+/// Calls `f(t0, t1)` for each part of [t_min, t_max] the ray spends inside
+/// the boundary of `medium`, in order, until it returns false. Boundary
+/// crossings alternate between entering and leaving from the first one on,
+/// so a boundary that is not convex makes several parts.
+#[inline(always)]
+fn medium_intervals<F: FnMut(f32, f32) -> bool>(scene: &Scene, medium: &Medium, ray: Ray, t_min: f32, t_max: f32, mut f: F) {
+    let boundary_hit = |t_min: f32, t_max: f32| {
+        let prototypes = scene.prototypes;
+        let mesh = &scene.mesh;
+        traverse(scene.prototype_bvh, medium.boundary as usize, ray, t_min, t_max, |i, closest_so_far| {
+            if i < prototypes.len() {
+                prototypes[i].hit(mesh, i, ray, t_min, closest_so_far)
+            }
+            else {
+                None
+            }
+        })
+    };
+    let mut enter_after = core::f32::NEG_INFINITY;
+    while let Some(enter) = boundary_hit(enter_after, core::f32::INFINITY) {
+        if enter.t >= t_max {
+            return;
+        }
+        let exit = match boundary_hit(enter.t + 0.0001, core::f32::INFINITY) {
+            Some(exit) => exit.t,
+            None => return,
+        };
+        let t0 = if enter.t < t_min { t_min } else { enter.t };
+        let t1 = if exit > t_max { t_max } else { exit };
+        if t0 < t1 && !f(t0, t1) {
+            return;
+        }
+        enter_after = exit + 0.0001;
+    }
+}
+
+pub fn hit(scene: &Scene, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    if scene.bvh.is_empty() {
+        return hit_brute_force(scene, ray, t_min, t_max);
+    }
+    let mut res = traverse(scene.bvh, 0, ray, t_min, t_max, |i, closest_so_far| {
+        if i < scene.objects.len() {
+            hit_object(scene, i, ray, t_min, closest_so_far)
+        }
+        else {
+            None
+        }
+    });
+    let mut closest_so_far = match res {
+        Some(ref rec) => rec.t,
+        None => t_max,
+    };
+    for i in scene.bounded_len..scene.objects.len() {
+        if let Some(rec) = hit_object(scene, i, ray, t_min, closest_so_far) {
+            closest_so_far = rec.t;
+            res = Some(rec);
+        }
+    }
+    res
+}
+
+/// Walks the BVH rooted at `nodes[root]` and returns the closest hit that
+/// `hit_leaf_object(i, closest_so_far)` reports for the objects of the leaves
+/// the ray reaches.
+#[inline(always)]
+fn traverse<F: FnMut(usize, f32) -> Option<HitRecord>>(nodes: &[BvhNode], root: usize, ray: Ray, t_min: f32, t_max: f32, mut hit_leaf_object: F) -> Option<HitRecord> {
+    let mut res = None;
+    let mut closest_so_far = t_max;
+    let mut stack = [0u32; BVH_MAX_DEPTH];
+    let mut stack_len = 0;
+    let mut node_id = root;
+    if node_id >= nodes.len() {
+        return None;
+    }
+    loop {
+        let node = nodes[node_id];
+        let mut next = None;
+        if node.bounds.hit(ray, t_min, closest_so_far) {
+            if node.is_leaf() {
+                for i in node.offset as usize..(node.offset + node.count) as usize {
+                    if let Some(rec) = hit_leaf_object(i, closest_so_far) {
+                        closest_so_far = rec.t;
+                        res = Some(rec);
+                    }
+                }
+            }
+            else {
+                // Visit the child on the near side of the split first so that
+                // closest_so_far shrinks early and culls the far one.
+                let (near, far) = if ray.direction().i(node.axis as usize) < 0.0 {
+                    (node.offset as usize, node_id + 1)
+                }
+                else {
+                    (node_id + 1, node.offset as usize)
+                };
+                // Only a tree deeper than the builder makes overflows the stack.
+                debug_assert!(stack_len < BVH_MAX_DEPTH);
+                if stack_len < BVH_MAX_DEPTH {
+                    stack[stack_len] = far as u32;
+                    stack_len += 1;
+                }
+                next = Some(near);
+            }
+        }
+        node_id = match next {
+            Some(near) => near,
+            None => {
+                if stack_len == 0 {
+                    break;
+                }
+                stack_len -= 1;
+                stack[stack_len] as usize
+            }
+        };
+        if node_id >= nodes.len() {
+            break;
+        }
+    }
+    res
+}
+
+/// Hits object `object_id`, descending into the prototype of an instance.
+#[inline(always)]
+fn hit_object(scene: &Scene, object_id: usize, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    let object = &scene.objects[object_id];
+    match object.shape {
+        ObjectShape::Instance{root,transform,..} => {
+            if transform as usize >= scene.transforms.len() {
+                return None;
             }
+            let transform = scene.transforms[transform as usize];
+            let local = transform.ray_to_object(ray);
+            let prototypes = scene.prototypes;
+            let mesh = &scene.mesh;
+            let mut rec = traverse(scene.prototype_bvh, root as usize, local, t_min, t_max, |i, closest_so_far| {
+                if i < prototypes.len() {
+                    prototypes[i].hit(mesh, i, local, t_min, closest_so_far)
+                }
+                else {
+                    None
+                }
+            })?;
+            rec.p = ray.point_at_parameter(rec.t);
+            rec.normal = transform.normal_to_world(rec.normal);
+            rec.geometric_normal = transform.normal_to_world(rec.geometric_normal);
+            rec.tangent = transform.to_world.transform_vector(rec.tangent);
+            rec.bitangent = transform.to_world.transform_vector(rec.bitangent);
+            rec.object_id = object_id;
+            Some(rec)
+        }
+        _ => object.hit(&scene.mesh, object_id, ray, t_min, t_max),
+    }
+}
+
+/// Tests the ray against every object. `hit` falls back to it when the scene has no BVH.
+pub fn hit_brute_force(scene: &Scene, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    let mut res = None;
+    let mut closest_so_far = t_max;
+    for i in 0..scene.objects.len() {
+        if let Some(rec) = hit_object(scene, i, ray, t_min, closest_so_far) {
+            closest_so_far = rec.t;
+            res = Some(rec);
         }
     }
     res
 }
 
-#[cfg(target_arch = "nvptx64")]
+/// Traces the `i`-th sample of an `h * w * ray_per_pixel` launch and returns the
+/// index of the pixel it belongs to together with its contribution to that pixel.
+#[inline(always)]
+pub fn sample(scene: &Scene, h: usize, w: usize, ray_per_pixel: usize, i: usize) -> (usize, Vec3) {
+    let x = i / ray_per_pixel % w;
+    let y = h - i / ray_per_pixel / w - 1;
+    let seed = i as u32;
+    let mut xorshift = XorShift::new(seed);
+    for _ in 0..i % ray_per_pixel {
+        xorshift.gen_u32();
+    }
+    let mut res = Vec3{x:0.0, y: 0.0, z: 0.0};
+    let camera = scene.camera;
+    let u = (x as f32 + xorshift.gen_f32()) / w as f32;
+    let v = (y as f32 + xorshift.gen_f32()) / h as f32;
+    let ray = camera.get_ray(&mut xorshift, u, v);
+    if scene.path.spectral {
+        let lambda = sample_wavelength(&mut xorshift);
+        res += spectrum_to_rgb(color(scene, &mut xorshift, ray, Some(lambda)).x, lambda);
+    }
+    else {
+        res += color(scene, &mut xorshift, ray, None);
+    }
+    // Linear radiance; the host encodes the averaged pixel for display.
+    res /= ray_per_pixel as f32;
+    (i / ray_per_pixel, res)
+}
+
 unsafe fn atomic_add_f32(ptr: *mut f32, x: f32) {
     loop {
         let old1 = *ptr;
         let old = old1.to_bits();
         let new = (old1 + x).to_bits();
-        let (res, _) = core::intrinsics::atomic_cxchg::<u32>(ptr as *mut u32, old, new);
+        let (res, _) = crate::arch::atomic_cxchg::<u32>(ptr as *mut u32, old, new);
         if res == old {
             break;
         }
@@ -63,34 +391,14 @@ unsafe fn atomic_add_f32(ptr: *mut f32, x: f32) {
 }
 
 #[no_mangle]
-#[cfg(not(target_arch = "nvptx64"))]
-pub extern "ptx-kernel" fn ray_trace(args: &RayTraceArgs) {}
-
-#[no_mangle]
-#[cfg(target_arch = "nvptx64")]
 pub extern "ptx-kernel" fn ray_trace(args: &RayTraceArgs) {
-    let image_len = args.image_len;
     let h = args.h;
     let w = args.w;
-    let i = unsafe { core::arch::nvptx::_block_idx_x() * core::arch::nvptx::_block_dim_x() + core::arch::nvptx::_thread_idx_x() } as isize;
+    let i = unsafe { crate::arch::_block_idx_x() * crate::arch::_block_dim_x() + crate::arch::_thread_idx_x() } as isize;
     let ray_per_pixel = args.ray_per_pixel;
-    if h != 0 && w != 0 && ray_per_pixel != 0 {
-        let x = i as usize / ray_per_pixel % w;
-        let y = h - i as usize / ray_per_pixel / w - 1;
-        let seed = i as u32;
-        let mut xorshift = XorShift::new(seed);
-        for _ in 0..i as usize % ray_per_pixel {
-            xorshift.gen_u32();
-        }
-        let mut res = Vec3{x:0.0, y: 0.0, z: 0.0};
-        let camera = args.camera;
-        let u = (x as f32 + xorshift.gen_f32()) / w as f32;
-        let v = (y as f32 + xorshift.gen_f32()) / h as f32;
-        let ray = camera.get_ray(&mut xorshift, u, v);
-        let col = color(args, &mut xorshift, ray);
-        res += col;
-        res = res.sqrt();
-        res /= ray_per_pixel as f32;
+    if h != 0 && w != 0 && ray_per_pixel != 0 && (i as usize) < h * w * ray_per_pixel {
+        let scene = args.scene();
+        let (i, res) = sample(&scene, h, w, ray_per_pixel, i as usize);
 
         let vec3 = Vec3::new();
         let x_offset = &vec3.x as *const f32 as usize - &vec3 as *const Vec3 as usize;
@@ -98,7 +406,6 @@ pub extern "ptx-kernel" fn ray_trace(args: &RayTraceArgs) {
         let z_offset = &vec3.z as *const f32 as usize - &vec3 as *const Vec3 as usize;
 
         unsafe {
-            let i = i as usize / ray_per_pixel;
             if i < args.image.len() {
                 let p = args.image[i].get();
                 atomic_add_f32((p as usize + x_offset) as *mut f32, res.x);
@@ -108,3 +415,108 @@ pub extern "ptx-kernel" fn ray_trace(args: &RayTraceArgs) {
         }
     }
 }
+
+/// Alternative to `ray_trace` with one thread per pixel: each thread traces all
+/// samples of its pixel and stores their sum, so no atomics are needed.
+#[no_mangle]
+pub extern "ptx-kernel" fn ray_trace_pixel(args: &RayTraceArgs) {
+    let h = args.h;
+    let w = args.w;
+    let i = unsafe { crate::arch::_block_idx_x() * crate::arch::_block_dim_x() + crate::arch::_thread_idx_x() } as isize;
+    let ray_per_pixel = args.ray_per_pixel;
+    if h != 0 && w != 0 && ray_per_pixel != 0 && (i as usize) < h * w {
+        let scene = args.scene();
+        let i = i as usize;
+        let mut sum = Vec3::new();
+        for j in i * ray_per_pixel..(i + 1) * ray_per_pixel {
+            let (_, res) = sample(&scene, h, w, ray_per_pixel, j);
+            sum += res;
+        }
+        unsafe {
+            if i < args.image.len() {
+                *args.image[i].get() = sum;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aabb::*;
+    use crate::background::*;
+    use crate::camera::*;
+    use crate::mesh::*;
+    use crate::path::*;
+
+    const DENSITY: f32 = 0.5;
+
+    /// Faces of the box [x0, x1] x [-1, 1] x [-1, 1].
+    fn box_faces(x0: f32, x1: f32) -> [ObjectShape; 6] {
+        [
+            ObjectShape::XyRect{x0, x1, y0: -1.0, y1: 1.0, k: 1.0, flip: false},
+            ObjectShape::XyRect{x0, x1, y0: -1.0, y1: 1.0, k: -1.0, flip: true},
+            ObjectShape::XzRect{x0, x1, z0: -1.0, z1: 1.0, k: 1.0, flip: false},
+            ObjectShape::XzRect{x0, x1, z0: -1.0, z1: 1.0, k: -1.0, flip: true},
+            ObjectShape::YzRect{y0: -1.0, y1: 1.0, z0: -1.0, z1: 1.0, k: x1, flip: false},
+            ObjectShape::YzRect{y0: -1.0, y1: 1.0, z0: -1.0, z1: 1.0, k: x0, flip: true},
+        ]
+    }
+
+    /// Scene holding only a medium bounded by `boundary`, whose shapes span
+    /// x in [0, 4].
+    fn scene<'a>(boundary: &'a [ObjectShape], bvh: &'a [BvhNode], media: &'a [Medium]) -> Scene<'a> {
+        let zero = Vec3::new();
+        Scene {
+            objects: &[],
+            bvh: &[],
+            bounded_len: 0,
+            mesh: MeshBuffers{vertices: &[], normals: &[], texcoords: &[], indices: &[]},
+            prototypes: boundary,
+            prototype_bvh: bvh,
+            transforms: &[],
+            media,
+            texels: &[],
+            fog: Fog::none(),
+            lights: &[],
+            background: Background::Constant{color: zero},
+            environment: &[],
+            camera: Camera{origin: zero, lower_left_corner: zero, horizontal: zero, vertical: zero, u: zero, v: zero, w: zero, lens_radius: 0.0, time0: 0.0, time1: 0.0},
+            path: PathSettings::default(),
+        }
+    }
+
+    /// Checks the transmittance along the x axis through `boundary`, which
+    /// holds `length` of medium, and the fraction of rays `sample_media` lets
+    /// through.
+    fn check(boundary: &[ObjectShape], length: f32) {
+        let bounds = Aabb{min: Vec3{x: -0.1, y: -1.1, z: -1.1}, max: Vec3{x: 4.1, y: 1.1, z: 1.1}};
+        let bvh = [BvhNode{bounds, offset: 0, count: boundary.len() as u32, axis: 0}];
+        let media = [Medium{boundary: 0, density: DENSITY, material: ObjectMaterial::Isotropic{albedo: Vec3{x: 1.0, y: 1.0, z: 1.0}}}];
+        let scene = scene(boundary, &bvh, &media);
+        let expected = exp(-DENSITY * length);
+        // A direction of length 2 checks that distances are measured in space.
+        let ray = Ray::new_from_origin_and_direction(Vec3{x: -1.0, y: 0.1, z: 0.2}, Vec3{x: 2.0, y: 0.0, z: 0.0});
+        let t = transmittance(&scene, ray, 0.0, 3.0);
+        assert!(abs(t - expected) < 1e-5, "transmittance {} != {}", t, expected);
+
+        let mut xorshift = XorShift::new(1);
+        let n = 1 << 16;
+        let escaped = (0..n).filter(|_| sample_media(&scene, &mut xorshift, ray, 0.0, 3.0).is_none()).count();
+        let escaped = escaped as f32 / n as f32;
+        assert!(abs(escaped - expected) < 0.01, "{} of the rays escaped, expected {}", escaped, expected);
+    }
+
+    #[test]
+    fn box_medium_transmittance() {
+        check(&box_faces(0.0, 1.5), 1.5);
+    }
+
+    #[test]
+    fn medium_in_two_parts_transmittance() {
+        let a = box_faces(0.0, 1.0);
+        let b = box_faces(2.0, 4.0);
+        let boundary = [a[0], a[1], a[2], a[3], a[4], a[5], b[0], b[1], b[2], b[3], b[4], b[5]];
+        check(&boundary, 3.0);
+    }
+}
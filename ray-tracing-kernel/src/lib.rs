@@ -6,11 +6,29 @@
 #[macro_use]
 extern crate cuda_tools;
 
+pub mod arch;
 pub mod vec3;
+pub mod math;
+pub mod matrix;
 pub mod xorshift;
+pub mod onb;
 pub mod camera;
 pub mod hit_record;
+pub mod aabb;
+pub mod bvh;
 pub mod object;
+pub mod bsdf;
+pub mod mesh;
+pub mod medium;
+pub mod light;
+pub mod microfacet;
+pub mod normal_map;
+pub mod path;
+pub mod principled;
+pub mod texture;
+pub mod background;
 pub mod ray_trace_args;
+pub mod scene;
+pub mod spectrum;
 pub mod ray;
 pub mod kernel;
@@ -0,0 +1,124 @@
+use crate::vec3::*;
+use crate::math::*;
+use crate::object::*;
+use crate::onb::*;
+use crate::hit_record::*;
+use crate::xorshift::*;
+
+/// An emissive object that is sampled directly: a sphere or a rectangle whose
+/// material is `ObjectMaterial::DiffuseLight`.
+#[derive(Clone,Copy)]
+pub struct Light {
+    pub object: u32,
+}
+
+/// A direction toward a light, the distance to the point sampled on it and
+/// the solid angle density the direction was drawn with.
+#[derive(Clone,Copy)]
+pub struct LightSample {
+    pub direction: Vec3,
+    pub distance: f32,
+    pub pdf: f32,
+}
+
+/// Whether `sample_light` can sample `shape`.
+#[inline(always)]
+pub fn is_sampleable(shape: &ObjectShape) -> bool {
+    match *shape {
+        ObjectShape::Sphere{radius,..} => radius > 0.0,
+        ObjectShape::XyRect{..} | ObjectShape::XzRect{..} | ObjectShape::YzRect{..} => true,
+        _ => false,
+    }
+}
+
+/// The cosine of the half angle of the cone `sphere` subtends from `origin`,
+/// or `None` when `origin` is inside it.
+#[inline(always)]
+fn sphere_cos_theta_max(center: Vec3, radius: f32, origin: Vec3) -> Option<f32> {
+    let dist2 = (center - origin).squared_length();
+    if dist2 <= radius * radius {
+        return None;
+    }
+    Some(sqrt(1.0 - radius * radius / dist2))
+}
+
+/// The axis index of the normal and the extents of a rectangle.
+#[inline(always)]
+fn rect(shape: &ObjectShape) -> Option<(usize, usize, usize, f32, f32, f32, f32, f32)> {
+    match *shape {
+        ObjectShape::XyRect{x0,x1,y0,y1,k,..} => Some((2, 0, 1, x0, x1, y0, y1, k)),
+        ObjectShape::XzRect{x0,x1,z0,z1,k,..} => Some((1, 0, 2, x0, x1, z0, z1, k)),
+        ObjectShape::YzRect{y0,y1,z0,z1,k,..} => Some((0, 1, 2, y0, y1, z0, z1, k)),
+        _ => None,
+    }
+}
+
+#[inline(always)]
+fn set_axis(p: &mut Vec3, axis: usize, value: f32) {
+    match axis {
+        0 => p.x = value,
+        1 => p.y = value,
+        _ => p.z = value,
+    }
+}
+
+/// Samples a direction from `origin` toward `shape`: uniformly within the cone
+/// of a sphere, uniformly by area on a rectangle.
+#[inline(always)]
+pub fn sample_light(shape: &ObjectShape, xorshift: &mut XorShift, origin: Vec3) -> Option<LightSample> {
+    if let ObjectShape::Sphere{center,radius} = *shape {
+        let cos_theta_max = sphere_cos_theta_max(center, radius, origin)?;
+        let r1 = xorshift.gen_f32();
+        let r2 = xorshift.gen_f32();
+        let z = 1.0 + r2 * (cos_theta_max - 1.0);
+        let phi = 2.0 * PI * r1;
+        let s = sqrt(1.0 - z * z);
+        let direction = Onb::from_w(center - origin).local(Vec3{x: cos(phi) * s, y: sin(phi) * s, z});
+        // Nearest root of |origin + t * direction - center| = radius.
+        let oc = origin - center;
+        let b = Vec3::dot(oc, direction);
+        let c = oc.squared_length() - radius * radius;
+        let discriminant = b * b - c;
+        let distance = -b - sqrt(if discriminant > 0.0 { discriminant } else { 0.0 });
+        let pdf = 1.0 / (2.0 * PI * (1.0 - cos_theta_max));
+        return Some(LightSample{direction, distance, pdf});
+    }
+    let (a, b, c, b0, b1, c0, c1, k) = rect(shape)?;
+    let mut p = Vec3::new();
+    set_axis(&mut p, a, k);
+    set_axis(&mut p, b, b0 + xorshift.gen_f32() * (b1 - b0));
+    set_axis(&mut p, c, c0 + xorshift.gen_f32() * (c1 - c0));
+    let to_light = p - origin;
+    let distance = to_light.length();
+    let direction = to_light / distance;
+    let cosine = abs(direction.i(a));
+    if !(cosine > 1e-6) {
+        return None;
+    }
+    let area = (b1 - b0) * (c1 - c0);
+    let pdf = distance * distance / (cosine * area);
+    Some(LightSample{direction, distance, pdf})
+}
+
+/// The density `sample_light` would have drawn the unit vector `direction`
+/// from `origin` with, given the hit `rec` it produces on `shape`.
+#[inline(always)]
+pub fn light_pdf(shape: &ObjectShape, origin: Vec3, direction: Vec3, rec: &HitRecord) -> f32 {
+    if let ObjectShape::Sphere{center,radius} = *shape {
+        return match sphere_cos_theta_max(center, radius, origin) {
+            Some(cos_theta_max) => 1.0 / (2.0 * PI * (1.0 - cos_theta_max)),
+            None => 0.0,
+        };
+    }
+    match rect(shape) {
+        Some((a, _, _, b0, b1, c0, c1, _)) => {
+            let distance = (rec.p - origin).length();
+            let cosine = abs(direction.i(a));
+            if !(cosine > 1e-6) {
+                return 0.0;
+            }
+            distance * distance / (cosine * (b1 - b0) * (c1 - c0))
+        }
+        None => 0.0,
+    }
+}
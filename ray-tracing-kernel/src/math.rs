@@ -0,0 +1,178 @@
+//! `f32` functions missing from `core`, as intrinsics where PTX has an
+//! instruction for them and as polynomial approximations otherwise: the
+//! other intrinsics become calls into a math library the kernel is not
+//! linked with.
+
+use core::intrinsics;
+
+pub const PI: f32 = 3.14159265358979323846;
+const FRAC_2_PI: f32 = 0.636619772367581343076;
+const LN_2: f32 = 0.693147180559945309417;
+const LOG2_E: f32 = 1.44269504088896340736;
+const SQRT_2: f32 = 1.41421356237309504880;
+
+#[inline(always)]
+pub fn sqrt(x: f32) -> f32 {
+    unsafe { intrinsics::sqrtf32(x) }
+}
+
+/// `(sin(x), cos(x))`, absolute error below 1e-6 for |x| up to 1000.
+#[inline(always)]
+pub fn sin_cos(x: f32) -> (f32, f32) {
+    // x = k pi/2 + r with |r| <= pi/4, pi/2 split in two so that k pi/2 is
+    // exact for the high part.
+    let k = floor(x * FRAC_2_PI + 0.5);
+    let r = (x - k * 1.5703125) - k * 4.8382679e-4;
+    let r2 = r * r;
+    let s = r * (1.0 + r2 * (-1.0 / 6.0 + r2 * (1.0 / 120.0 + r2 * (-1.0 / 5040.0 + r2 * (1.0 / 362880.0)))));
+    let c = 1.0 + r2 * (-0.5 + r2 * (1.0 / 24.0 + r2 * (-1.0 / 720.0 + r2 * (1.0 / 40320.0))));
+    match (k as i32) & 3 {
+        0 => (s, c),
+        1 => (c, -s),
+        2 => (-s, -c),
+        _ => (-c, s),
+    }
+}
+
+#[inline(always)]
+pub fn sin(x: f32) -> f32 {
+    sin_cos(x).0
+}
+
+#[inline(always)]
+pub fn cos(x: f32) -> f32 {
+    sin_cos(x).1
+}
+
+#[inline(always)]
+pub fn floor(x: f32) -> f32 {
+    unsafe { intrinsics::floorf32(x) }
+}
+
+/// Relative error below 1e-6.
+#[inline(always)]
+pub fn exp(x: f32) -> f32 {
+    if x > 88.72 {
+        return core::f32::INFINITY;
+    }
+    if x < -87.0 {
+        return 0.0;
+    }
+    if x.is_nan() {
+        return x;
+    }
+    // x = k ln 2 + r with |r| <= ln 2 / 2, so exp(x) = 2^k exp(r).
+    let k = floor(x * LOG2_E + 0.5);
+    let r = (x - k * 0.693359375) + k * 2.12194440e-4;
+    let p = 1.0 + r * (1.0 + r * (0.5 + r * (1.0 / 6.0 + r * (1.0 / 24.0 + r * (1.0 / 120.0 + r * (1.0 / 720.0 + r * (1.0 / 5040.0)))))));
+    // 2^k built in two steps, as 2^128 is not a normal `f32`.
+    2.0 * p * f32::from_bits(((k as i32 + 126) as u32) << 23)
+}
+
+/// Natural logarithm, relative error below 1e-6.
+#[inline(always)]
+pub fn ln(x: f32) -> f32 {
+    if !(x > 0.0) {
+        return if x == 0.0 { core::f32::NEG_INFINITY } else { core::f32::NAN };
+    }
+    if x == core::f32::INFINITY {
+        return x;
+    }
+    let (x, e) = if x < core::f32::MIN_POSITIVE { (x * 8388608.0, -23) } else { (x, 0) };
+    // x = m 2^e with m in [sqrt(2) / 2, sqrt(2)].
+    let bits = x.to_bits();
+    let mut e = e + ((bits >> 23) as i32 - 127);
+    let mut m = f32::from_bits((bits & 0x007f_ffff) | 0x3f80_0000);
+    if m > SQRT_2 {
+        m *= 0.5;
+        e += 1;
+    }
+    // ln(m) = 2 atanh(s) for s = (m - 1) / (m + 1), |s| < 0.172.
+    let s = (m - 1.0) / (m + 1.0);
+    let s2 = s * s;
+    2.0 * s * (1.0 + s2 * (1.0 / 3.0 + s2 * (1.0 / 5.0 + s2 * (1.0 / 7.0 + s2 * (1.0 / 9.0))))) + e as f32 * LN_2
+}
+
+#[inline(always)]
+pub fn abs(x: f32) -> f32 {
+    unsafe { intrinsics::fabsf32(x) }
+}
+
+/// atan on [0, 1], absolute error below 1e-5.
+#[inline(always)]
+fn atan_unit(z: f32) -> f32 {
+    let z2 = z * z;
+    z * (0.99997726 + z2 * (-0.33262347 + z2 * (0.19354346 + z2 * (-0.11643287 + z2 * (0.05265332 + z2 * -0.01172120)))))
+}
+
+#[inline(always)]
+pub fn atan2(y: f32, x: f32) -> f32 {
+    let ax = abs(x);
+    let ay = abs(y);
+    if ax == 0.0 && ay == 0.0 {
+        return 0.0;
+    }
+    let mut r = if ay > ax { 0.5 * PI - atan_unit(ax / ay) } else { atan_unit(ay / ax) };
+    if x < 0.0 {
+        r = PI - r;
+    }
+    if y < 0.0 {
+        r = -r;
+    }
+    r
+}
+
+#[inline(always)]
+pub fn acos(x: f32) -> f32 {
+    let s = 1.0 - x * x;
+    atan2(sqrt(if s > 0.0 { s } else { 0.0 }), x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(name: &str, f: fn(f32) -> f32, reference: fn(f32) -> f32, xs: &[f32], relative: bool, tolerance: f32) {
+        for &x in xs {
+            let (a, e) = (f(x), reference(x));
+            let error = if relative { abs(a - e) / abs(e) } else { abs(a - e) };
+            assert!(error <= tolerance, "{}({}) = {}, expected {}", name, x, a, e);
+        }
+    }
+
+    fn range(from: f32, to: f32, n: usize) -> impl Iterator<Item = f32> {
+        (0..=n).map(move |i| from + (to - from) * i as f32 / n as f32)
+    }
+
+    #[test]
+    fn sin_cos_match_the_intrinsics() {
+        let mut xs = [0.0; 20001];
+        for (x, v) in xs.iter_mut().zip(range(-1000.0, 1000.0, 20000)) {
+            *x = v;
+        }
+        check("sin", sin, |x| unsafe { intrinsics::sinf32(x) }, &xs, false, 1e-6);
+        check("cos", cos, |x| unsafe { intrinsics::cosf32(x) }, &xs, false, 1e-6);
+    }
+
+    #[test]
+    fn exp_matches_the_intrinsic() {
+        let mut xs = [0.0; 20001];
+        for (x, v) in xs.iter_mut().zip(range(-86.0, 88.0, 20000)) {
+            *x = v;
+        }
+        check("exp", exp, |x| unsafe { intrinsics::expf32(x) }, &xs, true, 1e-6);
+        assert!(exp(-100.0) == 0.0 && exp(100.0) == core::f32::INFINITY && exp(0.0) == 1.0);
+        assert!(exp(core::f32::NAN).is_nan());
+    }
+
+    #[test]
+    fn ln_matches_the_intrinsic() {
+        let mut xs = [0.0; 20001];
+        for (x, v) in xs.iter_mut().zip(range(-120.0, 120.0, 20000)) {
+            *x = unsafe { intrinsics::exp2f32(v) };
+        }
+        check("ln", ln, |x| unsafe { intrinsics::logf32(x) }, &xs, false, 1e-6 * 120.0);
+        check("ln", ln, |x| unsafe { intrinsics::logf32(x) }, &[1e-40, 0.5, 0.999, 1.001, 2.0, 1e30], false, 1e-6);
+        assert!(ln(1.0) == 0.0 && ln(0.0) == core::f32::NEG_INFINITY && ln(-1.0).is_nan() && ln(core::f32::INFINITY) == core::f32::INFINITY);
+    }
+}
@@ -0,0 +1,211 @@
+use core::ops::Mul;
+use crate::vec3::*;
+use crate::ray::*;
+use crate::aabb::*;
+use crate::math::*;
+
+/// Affine transform, stored as the top three rows of a 4x4 matrix whose last
+/// row is (0, 0, 0, 1).
+#[repr(C)]
+#[derive(Clone,Copy)]
+pub struct Mat3x4 {
+    pub m: [[f32; 4]; 3],
+}
+
+impl Mul for Mat3x4 {
+    type Output = Mat3x4;
+
+    #[inline(always)]
+    fn mul(self, rhs: Mat3x4) -> Mat3x4 {
+        let a = &self.m;
+        let b = &rhs.m;
+        let mut m = [[0.0f32; 4]; 3];
+        for i in 0..3 {
+            for j in 0..4 {
+                m[i][j] = a[i][0] * b[0][j] + a[i][1] * b[1][j] + a[i][2] * b[2][j];
+            }
+            m[i][3] += a[i][3];
+        }
+        Mat3x4 { m }
+    }
+}
+
+impl Mat3x4 {
+    #[inline(always)]
+    pub fn identity() -> Mat3x4 {
+        Mat3x4 {
+            m: [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+            ],
+        }
+    }
+
+    #[inline(always)]
+    pub fn translation(t: Vec3) -> Mat3x4 {
+        Mat3x4 {
+            m: [
+                [1.0, 0.0, 0.0, t.x],
+                [0.0, 1.0, 0.0, t.y],
+                [0.0, 0.0, 1.0, t.z],
+            ],
+        }
+    }
+
+    #[inline(always)]
+    pub fn scaling(s: Vec3) -> Mat3x4 {
+        Mat3x4 {
+            m: [
+                [s.x, 0.0, 0.0, 0.0],
+                [0.0, s.y, 0.0, 0.0],
+                [0.0, 0.0, s.z, 0.0],
+            ],
+        }
+    }
+
+    /// Counter-clockwise rotation by `angle` radians about the x axis.
+    #[inline(always)]
+    pub fn rotation_x(angle: f32) -> Mat3x4 {
+        let (s, c) = (sin(angle), cos(angle));
+        Mat3x4 {
+            m: [
+                [1.0, 0.0, 0.0, 0.0],
+                [0.0, c, -s, 0.0],
+                [0.0, s, c, 0.0],
+            ],
+        }
+    }
+
+    #[inline(always)]
+    pub fn rotation_y(angle: f32) -> Mat3x4 {
+        let (s, c) = (sin(angle), cos(angle));
+        Mat3x4 {
+            m: [
+                [c, 0.0, s, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [-s, 0.0, c, 0.0],
+            ],
+        }
+    }
+
+    #[inline(always)]
+    pub fn rotation_z(angle: f32) -> Mat3x4 {
+        let (s, c) = (sin(angle), cos(angle));
+        Mat3x4 {
+            m: [
+                [c, -s, 0.0, 0.0],
+                [s, c, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+            ],
+        }
+    }
+
+    #[inline(always)]
+    pub fn transform_point(&self, p: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3 {
+            x: m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            y: m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            z: m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        }
+    }
+
+    #[inline(always)]
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3 {
+            x: m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            y: m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            z: m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        }
+    }
+
+    /// Multiplies `v` by the transpose of the linear part. Applied to the
+    /// inverse transform this maps normals the way `self` maps surfaces.
+    #[inline(always)]
+    pub fn transpose_transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3 {
+            x: m[0][0] * v.x + m[1][0] * v.y + m[2][0] * v.z,
+            y: m[0][1] * v.x + m[1][1] * v.y + m[2][1] * v.z,
+            z: m[0][2] * v.x + m[1][2] * v.y + m[2][2] * v.z,
+        }
+    }
+
+    /// The inverse transform, or `None` when the linear part is singular.
+    #[inline(always)]
+    pub fn inverse(&self) -> Option<Mat3x4> {
+        let m = &self.m;
+        let c00 = m[1][1] * m[2][2] - m[1][2] * m[2][1];
+        let c01 = m[1][2] * m[2][0] - m[1][0] * m[2][2];
+        let c02 = m[1][0] * m[2][1] - m[1][1] * m[2][0];
+        let det = m[0][0] * c00 + m[0][1] * c01 + m[0][2] * c02;
+        if !(abs(det) > 1e-12) {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let mut r = [[0.0f32; 4]; 3];
+        r[0][0] = c00 * inv_det;
+        r[1][0] = c01 * inv_det;
+        r[2][0] = c02 * inv_det;
+        r[0][1] = (m[0][2] * m[2][1] - m[0][1] * m[2][2]) * inv_det;
+        r[1][1] = (m[0][0] * m[2][2] - m[0][2] * m[2][0]) * inv_det;
+        r[2][1] = (m[0][1] * m[2][0] - m[0][0] * m[2][1]) * inv_det;
+        r[0][2] = (m[0][1] * m[1][2] - m[0][2] * m[1][1]) * inv_det;
+        r[1][2] = (m[0][2] * m[1][0] - m[0][0] * m[1][2]) * inv_det;
+        r[2][2] = (m[0][0] * m[1][1] - m[0][1] * m[1][0]) * inv_det;
+        let linear = Mat3x4 { m: r };
+        let t = linear.transform_vector(Vec3{x: m[0][3], y: m[1][3], z: m[2][3]});
+        r[0][3] = -t.x;
+        r[1][3] = -t.y;
+        r[2][3] = -t.z;
+        Some(Mat3x4 { m: r })
+    }
+}
+
+/// An object-to-world transform together with its inverse.
+#[repr(C)]
+#[derive(Clone,Copy)]
+pub struct Transform {
+    pub to_world: Mat3x4,
+    pub to_object: Mat3x4,
+}
+
+impl Transform {
+    #[inline(always)]
+    pub fn new(to_world: Mat3x4) -> Option<Transform> {
+        to_world.inverse().map(|to_object| Transform { to_world, to_object })
+    }
+
+    /// The ray in object space. The direction is not renormalized, so hit
+    /// distances are the same in both spaces.
+    #[inline(always)]
+    pub fn ray_to_object(&self, ray: Ray) -> Ray {
+        Ray::new_from_origin_direction_and_time(
+            self.to_object.transform_point(ray.origin()),
+            self.to_object.transform_vector(ray.direction()),
+            ray.time())
+    }
+
+    #[inline(always)]
+    pub fn normal_to_world(&self, normal: Vec3) -> Vec3 {
+        Vec3::unit_vector(self.to_object.transpose_transform_vector(normal))
+    }
+
+    /// World-space bounds of the object-space box `b`.
+    #[inline(always)]
+    pub fn bounding_box(&self, b: Aabb) -> Aabb {
+        let mut res = Aabb::empty();
+        for i in 0..8 {
+            let corner = Vec3 {
+                x: if i & 1 == 0 { b.min.x } else { b.max.x },
+                y: if i & 2 == 0 { b.min.y } else { b.max.y },
+                z: if i & 4 == 0 { b.min.z } else { b.max.z },
+            };
+            let p = self.to_world.transform_point(corner);
+            res = Aabb::surrounding(res, Aabb{min: p, max: p});
+        }
+        res
+    }
+}
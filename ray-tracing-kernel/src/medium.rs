@@ -0,0 +1,38 @@
+use crate::vec3::*;
+use crate::object::*;
+use crate::math::*;
+use crate::xorshift::*;
+
+/// Constant-density volume filling a closed boundary, whose shapes are stored
+/// as a prototype rooted at node `boundary` of the scene's prototype BVH.
+#[derive(Clone,Copy)]
+pub struct Medium {
+    pub boundary: u32,
+    pub density: f32,
+    /// Usually `ObjectMaterial::Isotropic`.
+    pub material: ObjectMaterial,
+}
+
+/// Homogeneous medium filling the whole scene, disabled when `density` is zero.
+#[derive(Clone,Copy)]
+pub struct Fog {
+    pub density: f32,
+    pub albedo: Vec3,
+}
+
+impl Fog {
+    #[inline(always)]
+    pub fn none() -> Fog {
+        Fog {
+            density: 0.0,
+            albedo: Vec3::new(),
+        }
+    }
+}
+
+/// Samples the distance a ray travels through a medium of `density` before
+/// scattering.
+#[inline(always)]
+pub fn free_flight_distance(xorshift: &mut XorShift, density: f32) -> f32 {
+    -ln(1.0 - xorshift.gen_f32()) / density
+}
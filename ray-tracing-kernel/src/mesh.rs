@@ -0,0 +1,73 @@
+use crate::vec3::*;
+
+/// Vertex and index buffers shared by every `ObjectShape::MeshTriangle`.
+///
+/// `normals` and `texcoords` are either empty or parallel to `vertices`; each
+/// entry of `indices` holds the vertex indices of one triangle.
+#[derive(Clone,Copy)]
+pub struct MeshBuffers<'a> {
+    pub vertices: &'a [Vec3],
+    pub normals: &'a [Vec3],
+    pub texcoords: &'a [[f32; 2]],
+    pub indices: &'a [[u32; 3]],
+}
+
+impl<'a> MeshBuffers<'a> {
+    #[inline(always)]
+    pub fn empty() -> MeshBuffers<'a> {
+        MeshBuffers {
+            vertices: &[],
+            normals: &[],
+            texcoords: &[],
+            indices: &[],
+        }
+    }
+
+    #[inline(always)]
+    pub fn vertices(&self, triangle: u32) -> Option<[Vec3; 3]> {
+        let triangle = triangle as usize;
+        if triangle >= self.indices.len() {
+            return None;
+        }
+        let [i0, i1, i2] = self.indices[triangle];
+        let (i0, i1, i2) = (i0 as usize, i1 as usize, i2 as usize);
+        if i0 < self.vertices.len() && i1 < self.vertices.len() && i2 < self.vertices.len() {
+            Some([self.vertices[i0], self.vertices[i1], self.vertices[i2]])
+        }
+        else {
+            None
+        }
+    }
+
+    #[inline(always)]
+    pub fn normals(&self, triangle: u32) -> Option<[Vec3; 3]> {
+        let triangle = triangle as usize;
+        if triangle >= self.indices.len() {
+            return None;
+        }
+        let [i0, i1, i2] = self.indices[triangle];
+        let (i0, i1, i2) = (i0 as usize, i1 as usize, i2 as usize);
+        if i0 < self.normals.len() && i1 < self.normals.len() && i2 < self.normals.len() {
+            Some([self.normals[i0], self.normals[i1], self.normals[i2]])
+        }
+        else {
+            None
+        }
+    }
+
+    #[inline(always)]
+    pub fn texcoords(&self, triangle: u32) -> Option<[[f32; 2]; 3]> {
+        let triangle = triangle as usize;
+        if triangle >= self.indices.len() {
+            return None;
+        }
+        let [i0, i1, i2] = self.indices[triangle];
+        let (i0, i1, i2) = (i0 as usize, i1 as usize, i2 as usize);
+        if i0 < self.texcoords.len() && i1 < self.texcoords.len() && i2 < self.texcoords.len() {
+            Some([self.texcoords[i0], self.texcoords[i1], self.texcoords[i2]])
+        }
+        else {
+            None
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use crate::vec3::*;
+use crate::math::*;
+use crate::onb::*;
+use crate::xorshift::*;
+
+/// Complex index of refraction `eta + i k` of a conductor, per RGB channel.
+#[derive(Clone,Copy)]
+pub struct ComplexIor {
+    pub eta: Vec3,
+    pub k: Vec3,
+}
+
+pub const GOLD: ComplexIor = ComplexIor {
+    eta: Vec3{x: 0.143, y: 0.374, z: 1.442},
+    k: Vec3{x: 3.983, y: 2.385, z: 1.603},
+};
+
+pub const COPPER: ComplexIor = ComplexIor {
+    eta: Vec3{x: 0.200, y: 0.924, z: 1.102},
+    k: Vec3{x: 3.912, y: 2.452, z: 2.142},
+};
+
+pub const ALUMINIUM: ComplexIor = ComplexIor {
+    eta: Vec3{x: 1.657, y: 0.880, z: 0.521},
+    k: Vec3{x: 9.224, y: 6.270, z: 4.837},
+};
+
+/// GGX alpha for a perceptual roughness in [0, 1]. Clamped away from zero,
+/// where the distribution degenerates into a mirror the sampling below
+/// cannot represent.
+#[inline(always)]
+pub fn alpha(roughness: f32) -> f32 {
+    (roughness * roughness).max(1e-3)
+}
+
+/// GGX distribution of the local microfacet normal `m`.
+#[inline(always)]
+pub fn ggx_d(m: Vec3, alpha: f32) -> f32 {
+    if m.z <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    let t = m.z * m.z * (a2 - 1.0) + 1.0;
+    a2 / (PI * t * t)
+}
+
+/// Smith masking of the local direction `v` (z along the macro normal).
+#[inline(always)]
+pub fn smith_g1(v: Vec3, alpha: f32) -> f32 {
+    let z2 = v.z * v.z;
+    if z2 == 0.0 {
+        return 0.0;
+    }
+    let tan2 = (v.x * v.x + v.y * v.y) / z2;
+    2.0 / (1.0 + sqrt(1.0 + alpha * alpha * tan2))
+}
+
+/// Microfacet normal sampled from the GGX distribution of normals visible
+/// from the local direction `v`, which must be above the surface (Heitz 2018).
+#[inline(always)]
+pub fn sample_visible_normal(v: Vec3, alpha: f32, u1: f32, u2: f32) -> Vec3 {
+    let vh = Vec3::unit_vector(Vec3{x: alpha * v.x, y: alpha * v.y, z: v.z});
+    let lensq = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if lensq > 0.0 {
+        Vec3{x: -vh.y, y: vh.x, z: 0.0} / sqrt(lensq)
+    }
+    else {
+        Vec3{x: 1.0, y: 0.0, z: 0.0}
+    };
+    let t2 = Vec3::cross(vh, t1);
+    let r = sqrt(u1);
+    let phi = 2.0 * PI * u2;
+    let p1 = r * cos(phi);
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * sqrt(1.0 - p1 * p1) + s * r * sin(phi);
+    let nh = p1 * t1 + p2 * t2 + sqrt((1.0 - p1 * p1 - p2 * p2).max(0.0)) * vh;
+    Vec3::unit_vector(Vec3{x: alpha * nh.x, y: alpha * nh.y, z: nh.z.max(0.0)})
+}
+
+/// Solid angle density of the local reflection direction `wi` when
+/// reflecting `wo` about visible normals sampled by `sample_visible_normal`.
+#[inline(always)]
+pub fn ggx_reflection_pdf(wo: Vec3, wi: Vec3, alpha: f32) -> f32 {
+    if wo.z <= 0.0 || wi.z <= 0.0 {
+        return 0.0;
+    }
+    let h = Vec3::unit_vector(wo + wi);
+    smith_g1(wo, alpha) * ggx_d(h, alpha) / (4.0 * wo.z)
+}
+
+/// Unpolarized Fresnel reflectance of a dielectric interface, `eta` being
+/// the ratio of the indices on the transmitted and incident sides.
+#[inline(always)]
+pub fn fresnel_dielectric(cos_i: f32, eta: f32) -> f32 {
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = sqrt(1.0 - sin2_t);
+    let rs = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    let rp = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    0.5 * (rs * rs + rp * rp)
+}
+
+#[inline(always)]
+fn fresnel_conductor_channel(cos_i: f32, eta: f32, k: f32) -> f32 {
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+    let t0 = eta2 - k2 - sin2;
+    let a2b2 = sqrt(t0 * t0 + 4.0 * eta2 * k2);
+    let t1 = a2b2 + cos2;
+    let a = sqrt(0.5 * (a2b2 + t0));
+    let t2 = 2.0 * cos_i * a;
+    let rs = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+    0.5 * (rs + rp)
+}
+
+/// Fresnel reflectance of a conductor seen from a dielectric of index 1.
+#[inline(always)]
+pub fn fresnel_conductor(cos_i: f32, ior: ComplexIor) -> Vec3 {
+    Vec3{
+        x: fresnel_conductor_channel(cos_i, ior.eta.x, ior.k.x),
+        y: fresnel_conductor_channel(cos_i, ior.eta.y, ior.k.y),
+        z: fresnel_conductor_channel(cos_i, ior.eta.z, ior.k.z),
+    }
+}
+
+/// Mirror image of `v` about the microfacet normal `m`.
+#[inline(always)]
+pub fn reflect_about(v: Vec3, m: Vec3) -> Vec3 {
+    2.0 * Vec3::dot(v, m) * m - v
+}
+
+/// Direction `v` (pointing away from the surface) refracts into across the
+/// microfacet normal `m`, or `None` on total internal reflection.
+#[inline(always)]
+pub fn refract_about(v: Vec3, m: Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = Vec3::dot(v, m);
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = sqrt(1.0 - sin2_t);
+    Some(-v / eta + (cos_i / eta - cos_t) * m)
+}
+
+/// Samples a rough dielectric boundary with outward normal `normal`, lit
+/// from the world direction `wo` (pointing away from the surface). Returns
+/// the sample weight, the world space direction and whether it refracted.
+#[inline(always)]
+pub fn sample_rough_dielectric(xorshift: &mut XorShift, normal: Vec3, wo: Vec3, ref_idx: f32, alpha: f32) -> Option<(f32, Vec3, bool)> {
+    let (normal, eta) = if Vec3::dot(wo, normal) > 0.0 {
+        (normal, ref_idx)
+    }
+    else {
+        (-normal, 1.0 / ref_idx)
+    };
+    let onb = Onb::from_w(normal);
+    let wo = onb.to_local(wo);
+    let m = sample_visible_normal(wo, alpha, xorshift.gen_f32(), xorshift.gen_f32());
+    // Choosing the lobe by its Fresnel weight cancels F out of both.
+    let wi = if xorshift.gen_f32() < fresnel_dielectric(Vec3::dot(wo, m), eta) {
+        reflect_about(wo, m)
+    }
+    else {
+        refract_about(wo, m, eta)?
+    };
+    let reflected = Vec3::dot(wo, m) * Vec3::dot(wi, m) > 0.0;
+    if (wi.z > 0.0) != reflected || wi.z == 0.0 {
+        return None;
+    }
+    Some((smith_g1(wi, alpha), onb.local(wi), !reflected))
+}
+
+/// BSDF times cosine of a rough dielectric boundary and the density
+/// `sample_rough_dielectric` draws `wi` with, for local directions with `wo`
+/// above the surface and `eta` the ratio of the indices below and above.
+/// Transmission is not scaled by 1 / eta^2, matching the sample weights.
+#[inline(always)]
+pub fn rough_dielectric_eval(wo: Vec3, wi: Vec3, eta: f32, alpha: f32) -> (f32, f32) {
+    if wo.z <= 0.0 || wi.z == 0.0 {
+        return (0.0, 0.0);
+    }
+    let reflected = wi.z > 0.0;
+    let h = if reflected { wo + wi } else { -(wo + eta * wi) };
+    // Zero only for wi = -wo and eta = 1, which scene files reject.
+    if !(h.squared_length() > 0.0) {
+        return (0.0, 0.0);
+    }
+    let h = Vec3::unit_vector(h);
+    let h = if h.z < 0.0 { -h } else { h };
+    let cos_o = Vec3::dot(wo, h);
+    let cos_i = Vec3::dot(wi, h);
+    if cos_o <= 0.0 || (cos_i > 0.0) != reflected {
+        return (0.0, 0.0);
+    }
+    let f = fresnel_dielectric(cos_o, eta);
+    // Density of `h` among the normals visible from `wo`.
+    let visible = smith_g1(wo, alpha) * cos_o * ggx_d(h, alpha) / wo.z;
+    let (lobe, jacobian) = if reflected {
+        (f, 1.0 / (4.0 * cos_o))
+    }
+    else {
+        let denom = cos_o + eta * cos_i;
+        (1.0 - f, eta * eta * -cos_i / (denom * denom))
+    };
+    let pdf = lobe * visible * jacobian;
+    (pdf * smith_g1(wi, alpha), pdf)
+}
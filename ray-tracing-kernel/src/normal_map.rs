@@ -0,0 +1,80 @@
+use crate::vec3::*;
+use crate::hit_record::*;
+use crate::texture::*;
+
+/// Perturbation of the shading normal by an image texture, oriented by the
+/// tangent frame of the hit.
+#[derive(Clone,Copy)]
+pub enum NormalMap {
+    None,
+    /// Tangent-space normals encoded as `0.5 * (n + 1)`, green along
+    /// increasing `v`. `strength` scales the tilt away from the surface.
+    Normal {
+        map: Texture,
+        strength: f32,
+    },
+    /// Heights, the average of the channels, displacing the surface along
+    /// its normal by `scale` times their value.
+    Bump {
+        map: Texture,
+        scale: f32,
+    },
+}
+
+/// Unit vector along `a` without its component along the unit `n`, or
+/// `None` if nothing is left.
+#[inline(always)]
+fn orthogonalize(a: Vec3, n: Vec3) -> Option<Vec3> {
+    let a = a - Vec3::dot(a, n) * n;
+    let len = a.length();
+    if len > 1e-8 { Some(a / len) } else { None }
+}
+
+#[inline(always)]
+fn height(map: &Texture, texels: &[Vec3], u: f32, v: f32, p: Vec3) -> f32 {
+    let c = map.value(texels, u, v, p);
+    (c.x + c.y + c.z) / 3.0
+}
+
+impl NormalMap {
+    /// Shading normal at the hit, `rec.normal` where the map is missing or
+    /// the surface has no tangent frame.
+    #[inline(always)]
+    pub fn apply(&self, texels: &[Vec3], rec: &HitRecord) -> Vec3 {
+        let n = Vec3::unit_vector(rec.normal);
+        let t = match orthogonalize(rec.tangent, n) {
+            Some(t) => t,
+            None => return n,
+        };
+        match *self {
+            NormalMap::None => n,
+            NormalMap::Normal{map,strength} => {
+                // The frame keeps the handedness of (tangent, bitangent, normal),
+                // which mirrored texture coordinates or a flipped normal change.
+                let b = Vec3::cross(n, t);
+                let b = if Vec3::dot(b, rec.bitangent) < 0.0 { -b } else { b };
+                let c = map.value(texels, rec.u, rec.v, rec.p);
+                let m = Vec3{x: strength * (2.0 * c.x - 1.0), y: strength * (2.0 * c.y - 1.0), z: 2.0 * c.z - 1.0};
+                let shading = m.x * t + m.y * b + m.z * n;
+                if Vec3::dot(shading, n) > 0.0 { Vec3::unit_vector(shading) } else { n }
+            }
+            NormalMap::Bump{map,scale} => {
+                let surface_normal = Vec3::cross(rec.tangent, rec.bitangent);
+                if surface_normal.squared_length() == 0.0 {
+                    return n;
+                }
+                let (du, dv) = match map {
+                    Texture::Image{width,height,..} if width > 0 && height > 0 => (1.0 / width as f32, 1.0 / height as f32),
+                    _ => (1e-3, 1e-3),
+                };
+                let h = height(&map, texels, rec.u, rec.v, rec.p);
+                let dh_du = scale * (height(&map, texels, rec.u + du, rec.v, rec.p) - h) / du;
+                let dh_dv = scale * (height(&map, texels, rec.u, rec.v + dv, rec.p) - h) / dv;
+                // Normal of the displaced surface p + h n, to first order.
+                let shading = Vec3::cross(rec.tangent + dh_du * n, rec.bitangent + dh_dv * n);
+                let shading = if Vec3::dot(surface_normal, n) < 0.0 { -shading } else { shading };
+                if Vec3::dot(shading, n) > 0.0 { Vec3::unit_vector(shading) } else { n }
+            }
+        }
+    }
+}
@@ -1,7 +1,14 @@
 use crate::vec3::*;
 use crate::ray::*;
 use crate::hit_record::*;
-use crate::xorshift::*;
+use crate::aabb::*;
+use crate::mesh::*;
+use crate::texture::*;
+use crate::math::*;
+use crate::microfacet::*;
+use crate::principled::*;
+use crate::normal_map::*;
+use crate::spectrum::*;
 
 #[derive(Clone,Copy)]
 pub struct Object {
@@ -14,13 +21,77 @@ pub enum ObjectShape {
     Sphere {
         center: Vec3,
         radius: f32,
-    }
+    },
+    /// Sphere whose center moves linearly from `center0` at `time0` to
+    /// `center1` at `time1`, and stays put outside that interval.
+    MovingSphere {
+        center0: Vec3,
+        center1: Vec3,
+        time0: f32,
+        time1: f32,
+        radius: f32,
+    },
+    /// Front face is the counter-clockwise side. With `normals`, the shading
+    /// normal is interpolated from the per-vertex ones.
+    Triangle {
+        vertices: [Vec3; 3],
+        normals: Option<[Vec3; 3]>,
+    },
+    /// Triangle `triangle` of the shared mesh buffers, interpolating the
+    /// per-vertex normals when `smooth` and texture coordinates when `textured`.
+    MeshTriangle {
+        triangle: u32,
+        smooth: bool,
+        textured: bool,
+    },
+    /// Infinite plane through `point`, front face on the side `normal` points to.
+    Plane {
+        point: Vec3,
+        normal: Vec3,
+    },
+    /// Rectangle [x0, x1] x [y0, y1] in the plane z = k, facing +z unless `flip`.
+    XyRect {
+        x0: f32,
+        x1: f32,
+        y0: f32,
+        y1: f32,
+        k: f32,
+        flip: bool,
+    },
+    /// Rectangle [x0, x1] x [z0, z1] in the plane y = k, facing +y unless `flip`.
+    XzRect {
+        x0: f32,
+        x1: f32,
+        z0: f32,
+        z1: f32,
+        k: f32,
+        flip: bool,
+    },
+    /// Rectangle [y0, y1] x [z0, z1] in the plane x = k, facing +x unless `flip`.
+    YzRect {
+        y0: f32,
+        y1: f32,
+        z0: f32,
+        z1: f32,
+        k: f32,
+        flip: bool,
+    },
+    /// The prototype whose BVH is rooted at node `root` of the scene's
+    /// prototype BVH, placed by transform `transform`. `bounds` are its world
+    /// space bounds. The material of the instancing object applies to every
+    /// prototype surface.
+    Instance {
+        root: u32,
+        transform: u32,
+        bounds: Aabb,
+    },
 }
 
 #[derive(Clone,Copy)]
 pub enum ObjectMaterial {
     Lambertian {
-        albedo: Vec3,
+        albedo: Texture,
+        normal_map: NormalMap,
     },
     Metal {
         albedo: Vec3,
@@ -28,100 +99,297 @@ pub enum ObjectMaterial {
     },
     Dielectric {
         ref_idx: f32,
+    },
+    /// Smooth dielectric whose index of refraction depends on the
+    /// wavelength. Only spectral rendering shows the dispersion; RGB
+    /// rendering uses the index at `LAMBDA_D`.
+    DispersiveDielectric {
+        ior: Ior,
+    },
+    /// GGX microfacet conductor. `roughness` is perceptual, alpha being its
+    /// square.
+    Conductor {
+        ior: ComplexIor,
+        roughness: f32,
+    },
+    /// GGX microfacet dielectric boundary, reflecting and refracting with
+    /// the Fresnel probabilities of each sampled microfacet.
+    RoughDielectric {
+        ref_idx: f32,
+        roughness: f32,
+    },
+    /// Layered material covering most opaque and transmissive surfaces.
+    Principled(Principled),
+    /// Emits `emit` from both sides and scatters nothing.
+    DiffuseLight {
+        emit: Vec3,
+    },
+    /// Scatters uniformly in all directions: the phase function of media.
+    Isotropic {
+        albedo: Vec3,
+    },
+}
+
+impl ObjectMaterial {
+    #[inline(always)]
+    pub fn emitted(&self) -> Vec3 {
+        match *self {
+            ObjectMaterial::DiffuseLight{emit} => emit,
+            _ => Vec3::new(),
+        }
+    }
+
+    #[inline(always)]
+    pub fn normal_map(&self) -> NormalMap {
+        match *self {
+            ObjectMaterial::Lambertian{normal_map,..} => normal_map,
+            ObjectMaterial::Principled(principled) => principled.normal_map,
+            _ => NormalMap::None,
+        }
     }
 }
 
+/// Möller–Trumbore ray/triangle intersection.
 #[inline(always)]
-fn schlick(cosine: f32, ref_idx: f32) -> f32 {
-    let r0 = (1.0 - ref_idx) / (1.0 + ref_idx);
-    let r0 = r0 * r0;
-    let t = 1.0 - cosine;
-    r0 + (1.0 - r0) * t * t * t * t * t
+fn hit_triangle(vertices: [Vec3; 3], normals: Option<[Vec3; 3]>, texcoords: Option<[[f32; 2]; 3]>, object_id: usize, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    let [v0, v1, v2] = vertices;
+    let e1 = v1 - v0;
+    let e2 = v2 - v0;
+    let pvec = Vec3::cross(ray.direction(), e2);
+    let det = Vec3::dot(e1, pvec);
+    if -1e-12 < det && det < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+    let tvec = ray.origin() - v0;
+    let u = Vec3::dot(tvec, pvec) * inv_det;
+    if u < 0.0 || u > 1.0 {
+        return None;
+    }
+    let qvec = Vec3::cross(tvec, e1);
+    let v = Vec3::dot(ray.direction(), qvec) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = Vec3::dot(e2, qvec) * inv_det;
+    if t_min < t && t < t_max {
+        let p = ray.point_at_parameter(t);
+        let geometric_normal = Vec3::unit_vector(Vec3::cross(e1, e2));
+        let normal = match normals {
+            Some([n0, n1, n2]) => Vec3::unit_vector((1.0 - u - v) * n0 + u * n1 + v * n2),
+            None => geometric_normal,
+        };
+        let geometric_normal = if Vec3::dot(geometric_normal, normal) < 0.0 { -geometric_normal } else { geometric_normal };
+        // The edges are dp/du and dp/dv of the barycentrics; with texture
+        // coordinates they are solved for from the coordinate deltas instead.
+        let (u, v, tangent, bitangent) = match texcoords {
+            Some([t0, t1, t2]) => {
+                let (du1, dv1) = (t1[0] - t0[0], t1[1] - t0[1]);
+                let (du2, dv2) = (t2[0] - t0[0], t2[1] - t0[1]);
+                let uv_det = du1 * dv2 - dv1 * du2;
+                let (tangent, bitangent) = if uv_det != 0.0 {
+                    ((dv2 * e1 - dv1 * e2) / uv_det, (du1 * e2 - du2 * e1) / uv_det)
+                }
+                else {
+                    (e1, e2)
+                };
+                let w = 1.0 - u - v;
+                (w * t0[0] + u * t1[0] + v * t2[0], w * t0[1] + u * t1[1] + v * t2[1], tangent, bitangent)
+            }
+            None => (u, v, e1, e2),
+        };
+        return Some(HitRecord{t,p,normal,geometric_normal,tangent,bitangent,u,v,object_id});
+    }
+    None
 }
 
-impl ObjectMaterial {
+#[inline(always)]
+fn hit_sphere(center: Vec3, radius: f32, object_id: usize, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    let oc = ray.origin() - center;
+    let a = Vec3::dot(ray.direction(), ray.direction());
+    let b = Vec3::dot(oc, ray.direction());
+    let c = Vec3::dot(oc, oc) - radius * radius;
+    let discriminant = b * b - a * c;
+    if discriminant > 0.0 {
+        use core::intrinsics::sqrtf32;
+        let t = unsafe { (-b - sqrtf32(discriminant)) / a };
+        if t_min < t && t < t_max {
+            return Some(sphere_hit_record(center, radius, object_id, ray, t));
+        }
+        let t = unsafe { (-b + sqrtf32(discriminant)) / a };
+        if t_min < t && t < t_max {
+            return Some(sphere_hit_record(center, radius, object_id, ray, t));
+        }
+    }
+    None
+}
+
+#[inline(always)]
+fn sphere_hit_record(center: Vec3, radius: f32, object_id: usize, ray: Ray, t: f32) -> HitRecord {
+    let p = ray.point_at_parameter(t);
+    let normal = (p - center) / radius;
+    let d = Vec3::unit_vector(p - center);
+    let (u, v) = sphere_uv(d);
+    // Derivatives of p along the parallel and the meridian, which vanish at
+    // the poles.
+    let r = if radius < 0.0 { -radius } else { radius };
+    let ring = sqrt(d.x * d.x + d.z * d.z);
+    let tangent = (2.0 * PI * r) * Vec3{x: d.z, y: 0.0, z: -d.x};
+    let bitangent = if ring > 0.0 {
+        (PI * r / ring) * (Vec3{x: 0.0, y: 1.0, z: 0.0} - d.y * d)
+    }
+    else {
+        Vec3::new()
+    };
+    HitRecord{t,p,normal,geometric_normal: normal,tangent,bitangent,u,v,object_id}
+}
+
+/// Longitude and latitude of the unit vector `d` from the sphere center, both
+/// in [0, 1]: u = 0 at -x going around through +z, v = 0 at the bottom pole.
+#[inline(always)]
+fn sphere_uv(d: Vec3) -> (f32, f32) {
+    let phi = atan2(-d.z, d.x) + PI;
+    let theta = acos(-d.y);
+    (phi / (2.0 * PI), theta / PI)
+}
+
+#[inline(always)]
+fn sphere_bounding_box(center: Vec3, radius: f32) -> Aabb {
+    let r = if radius < 0.0 { -radius } else { radius };
+    let r = Vec3{x: r, y: r, z: r};
+    Aabb{min: center - r, max: center + r}
+}
+
+#[inline(always)]
+fn moving_center(center0: Vec3, center1: Vec3, time0: f32, time1: f32, time: f32) -> Vec3 {
+    if !(time1 > time0) {
+        return center0;
+    }
+    let s = (time - time0) / (time1 - time0);
+    let s = if s < 0.0 { 0.0 } else if s > 1.0 { 1.0 } else { s };
+    center0 + s * (center1 - center0)
+}
+
+#[inline(always)]
+fn hit_plane(point: Vec3, normal: Vec3, object_id: usize, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    let denom = Vec3::dot(ray.direction(), normal);
+    if -1e-12 < denom && denom < 1e-12 {
+        return None;
+    }
+    let t = Vec3::dot(point - ray.origin(), normal) / denom;
+    if t_min < t && t < t_max {
+        let p = ray.point_at_parameter(t);
+        let normal = Vec3::unit_vector(normal);
+        return Some(HitRecord{t,p,normal,geometric_normal: normal,tangent: Vec3::new(),bitangent: Vec3::new(),u: 0.0,v: 0.0,object_id});
+    }
+    None
+}
+
+#[inline(always)]
+fn axis_vector(axis: usize, length: f32) -> Vec3 {
+    match axis {
+        0 => Vec3{x: length, y: 0.0, z: 0.0},
+        1 => Vec3{x: 0.0, y: length, z: 0.0},
+        _ => Vec3{x: 0.0, y: 0.0, z: length},
+    }
+}
+
+/// Hits the rectangle [b0, b1] x [c0, c1] spanned by axes `b` and `c` in the
+/// plane where axis `a` equals `k`. `u` and `v` run from 0 to 1 along `b` and `c`.
+#[inline(always)]
+fn hit_rect(a: usize, b: usize, c: usize, b0: f32, b1: f32, c0: f32, c1: f32, k: f32, flip: bool, object_id: usize, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+    let t = (k - ray.origin().i(a)) / ray.direction().i(a);
+    if !(t_min < t && t < t_max) {
+        return None;
+    }
+    let p = ray.point_at_parameter(t);
+    let pb = p.i(b);
+    let pc = p.i(c);
+    if pb < b0 || pb > b1 || pc < c0 || pc > c1 {
+        return None;
+    }
+    let u = (pb - b0) / (b1 - b0);
+    let v = (pc - c0) / (c1 - c0);
+    let normal = axis_vector(a, if flip { -1.0 } else { 1.0 });
+    Some(HitRecord{t,p,normal,geometric_normal: normal,tangent: axis_vector(b, b1 - b0),bitangent: axis_vector(c, c1 - c0),u,v,object_id})
+}
+
+/// Bounds of a rectangle, padded along its normal axis so that it has volume.
+#[inline(always)]
+fn rect_bounding_box(a: usize, min: Vec3, max: Vec3) -> Aabb {
+    let pad = axis_vector(a, 1e-4);
+    Aabb{min: min - pad, max: max + pad}
+}
+
+#[inline(always)]
+fn triangle_bounding_box(vertices: [Vec3; 3]) -> Aabb {
+    let [v0, v1, v2] = vertices;
+    let b = Aabb::surrounding(Aabb{min: v0, max: v0}, Aabb{min: v1, max: v1});
+    Aabb::surrounding(b, Aabb{min: v2, max: v2})
+}
+
+impl Object {
     #[inline(always)]
-    pub fn scatter(&self, xorshift: &mut XorShift, ray_in: Ray, hit_record: HitRecord) -> Option<(Vec3,Ray)> {
+    pub fn bounding_box(&self, mesh: &MeshBuffers) -> Aabb {
+        self.shape.bounding_box(mesh)
+    }
+
+    #[inline(always)]
+    pub fn hit(&self, mesh: &MeshBuffers, object_id: usize, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        self.shape.hit(mesh, object_id, ray, t_min, t_max)
+    }
+}
+
+impl ObjectShape {
+    #[inline(always)]
+    pub fn bounding_box(&self, mesh: &MeshBuffers) -> Aabb {
         match *self {
-            ObjectMaterial::Lambertian{albedo} => {
-                let target = hit_record.p + hit_record.normal + Vec3::random_in_unit_sphere(xorshift);
-                let scattered = Ray::new_from_origin_and_direction(hit_record.p, target - hit_record.p);
-                let attenuation = albedo;
-                Some((attenuation, scattered))
+            ObjectShape::Sphere{center,radius} => sphere_bounding_box(center, radius),
+            ObjectShape::MovingSphere{center0,center1,radius,..} => {
+                Aabb::surrounding(sphere_bounding_box(center0, radius), sphere_bounding_box(center1, radius))
             }
-            ObjectMaterial::Metal{albedo,fuzz} => {
-                let reflected = Vec3::reflect(Vec3::unit_vector(ray_in.direction()), hit_record.normal);
-                let scattered = Ray::new_from_origin_and_direction(hit_record.p, reflected + fuzz * Vec3::random_in_unit_sphere(xorshift));
-                let attenuation = albedo;
-                if Vec3::dot(scattered.direction(), hit_record.normal) > 0.0 {
-                    Some((attenuation,scattered))
-                }
-                else {
-                    None
-                }
-            }
-            ObjectMaterial::Dielectric{ref_idx} => {
-                let reflected = Vec3::reflect(ray_in.direction(), hit_record.normal);
-                let attenuation = Vec3{x: 1.0, y: 1.0, z: 1.0};
-                let outward_normal;
-                let ni_over_nt;
-                let cosine;
-                if Vec3::dot(ray_in.direction(), hit_record.normal) > 0.0 {
-                    outward_normal = -hit_record.normal;
-                    ni_over_nt = ref_idx;
-                    cosine = ref_idx * Vec3::dot(ray_in.direction(), hit_record.normal) / ray_in.direction().length();
-                }
-                else {
-                    outward_normal = hit_record.normal;
-                    ni_over_nt = 1.0 / ref_idx;
-                    cosine = -Vec3::dot(ray_in.direction(), hit_record.normal) / ray_in.direction().length();
-                }
-                if let Some(refracted) = Vec3::refract(ray_in.direction(), outward_normal, ni_over_nt) {
-                    let reflect_prob = schlick(cosine, ref_idx);
-                    let scattered = if xorshift.gen_f32() < reflect_prob {
-                        Ray::new_from_origin_and_direction(hit_record.p, reflected)
-                    }
-                    else {
-                        Ray::new_from_origin_and_direction(hit_record.p, refracted)
-                    };
-                    Some((attenuation,scattered))
-                }
-                else {
-                    let scattered = Ray::new_from_origin_and_direction(hit_record.p, reflected);
-                    Some((attenuation,scattered))
+            ObjectShape::Triangle{vertices,..} => triangle_bounding_box(vertices),
+            ObjectShape::MeshTriangle{triangle,..} => {
+                match mesh.vertices(triangle) {
+                    Some(vertices) => triangle_bounding_box(vertices),
+                    None => Aabb::empty(),
                 }
             }
+            ObjectShape::Plane{..} => Aabb {
+                min: Vec3{x: core::f32::NEG_INFINITY, y: core::f32::NEG_INFINITY, z: core::f32::NEG_INFINITY},
+                max: Vec3{x: core::f32::INFINITY, y: core::f32::INFINITY, z: core::f32::INFINITY},
+            },
+            ObjectShape::XyRect{x0,x1,y0,y1,k,..} => rect_bounding_box(2, Vec3{x: x0, y: y0, z: k}, Vec3{x: x1, y: y1, z: k}),
+            ObjectShape::XzRect{x0,x1,z0,z1,k,..} => rect_bounding_box(1, Vec3{x: x0, y: k, z: z0}, Vec3{x: x1, y: k, z: z1}),
+            ObjectShape::YzRect{y0,y1,z0,z1,k,..} => rect_bounding_box(0, Vec3{x: k, y: y0, z: z0}, Vec3{x: k, y: y1, z: z1}),
+            ObjectShape::Instance{bounds,..} => bounds,
         }
     }
-}
 
-impl Object {
+    /// Hits every shape but `Instance`, which needs the scene's prototypes
+    /// and is handled by `kernel::hit`.
     #[inline(always)]
-    pub fn hit(&self, object_id: usize, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
-        match self.shape {
-            ObjectShape::Sphere{center,radius} => {
-                let oc = ray.origin() - center;
-                let a = Vec3::dot(ray.direction(), ray.direction());
-                let b = Vec3::dot(oc, ray.direction());
-                let c = Vec3::dot(oc, oc) - radius * radius;
-                let discriminant = b * b - a * c;
-                if discriminant > 0.0 {
-                    use core::intrinsics::sqrtf32;
-                    let t = unsafe { (-b - sqrtf32(discriminant)) / a };
-                    if t_min < t && t < t_max {
-                        let p = ray.point_at_parameter(t);
-                        let normal = (p - center) / radius;
-                        return Some(HitRecord{t,p,normal,object_id});
-                    }
-                    let t = unsafe { (-b + sqrtf32(discriminant)) / a };
-                    if t_min < t && t < t_max {
-                        let p = ray.point_at_parameter(t);
-                        let normal = (p - center) / radius;
-                        return Some(HitRecord{t,p,normal,object_id});
-                    }
+    pub fn hit(&self, mesh: &MeshBuffers, object_id: usize, ray: Ray, t_min: f32, t_max: f32) -> Option<HitRecord> {
+        match *self {
+            ObjectShape::Sphere{center,radius} => hit_sphere(center, radius, object_id, ray, t_min, t_max),
+            ObjectShape::MovingSphere{center0,center1,time0,time1,radius} => {
+                let center = moving_center(center0, center1, time0, time1, ray.time());
+                hit_sphere(center, radius, object_id, ray, t_min, t_max)
+            }
+            ObjectShape::Triangle{vertices,normals} => hit_triangle(vertices, normals, None, object_id, ray, t_min, t_max),
+            ObjectShape::MeshTriangle{triangle,smooth,textured} => {
+                let normals = if smooth { mesh.normals(triangle) } else { None };
+                let texcoords = if textured { mesh.texcoords(triangle) } else { None };
+                match mesh.vertices(triangle) {
+                    Some(vertices) => hit_triangle(vertices, normals, texcoords, object_id, ray, t_min, t_max),
+                    None => None,
                 }
-                None
             }
+            ObjectShape::Plane{point,normal} => hit_plane(point, normal, object_id, ray, t_min, t_max),
+            ObjectShape::XyRect{x0,x1,y0,y1,k,flip} => hit_rect(2, 0, 1, x0, x1, y0, y1, k, flip, object_id, ray, t_min, t_max),
+            ObjectShape::XzRect{x0,x1,z0,z1,k,flip} => hit_rect(1, 0, 2, x0, x1, z0, z1, k, flip, object_id, ray, t_min, t_max),
+            ObjectShape::YzRect{y0,y1,z0,z1,k,flip} => hit_rect(0, 1, 2, y0, y1, z0, z1, k, flip, object_id, ray, t_min, t_max),
+            ObjectShape::Instance{..} => None,
         }
     }
 }
@@ -0,0 +1,43 @@
+use crate::vec3::*;
+use crate::math::*;
+use crate::xorshift::*;
+
+/// Orthonormal basis whose `w` axis is a given direction.
+#[derive(Clone,Copy)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    #[inline(always)]
+    pub fn from_w(n: Vec3) -> Onb {
+        let w = Vec3::unit_vector(n);
+        let a = if abs(w.x) > 0.9 { Vec3{x: 0.0, y: 1.0, z: 0.0} } else { Vec3{x: 1.0, y: 0.0, z: 0.0} };
+        let v = Vec3::unit_vector(Vec3::cross(w, a));
+        let u = Vec3::cross(w, v);
+        Onb { u, v, w }
+    }
+
+    #[inline(always)]
+    pub fn local(&self, a: Vec3) -> Vec3 {
+        a.x * self.u + a.y * self.v + a.z * self.w
+    }
+
+    /// Inverse of `local`: coordinates of the world space vector `a`.
+    #[inline(always)]
+    pub fn to_local(&self, a: Vec3) -> Vec3 {
+        Vec3{x: Vec3::dot(a, self.u), y: Vec3::dot(a, self.v), z: Vec3::dot(a, self.w)}
+    }
+}
+
+/// Direction around +z distributed with density cos(theta) / pi.
+#[inline(always)]
+pub fn random_cosine_direction(xorshift: &mut XorShift) -> Vec3 {
+    let r1 = xorshift.gen_f32();
+    let r2 = xorshift.gen_f32();
+    let phi = 2.0 * PI * r1;
+    let r = sqrt(r2);
+    Vec3{x: cos(phi) * r, y: sin(phi) * r, z: sqrt(1.0 - r2)}
+}
@@ -0,0 +1,102 @@
+use crate::vec3::*;
+use crate::xorshift::*;
+
+/// How long `color` follows a path.
+#[derive(Clone,Copy)]
+pub struct PathSettings {
+    /// Bounces after which a path is cut off. Light it would have gathered
+    /// later is lost, so this is the only source of bias.
+    pub max_depth: u32,
+    /// Bounces after which paths are terminated by Russian roulette.
+    pub rr_depth: u32,
+    /// Upper bound of the survival probability, which otherwise follows the
+    /// largest component of the path throughput.
+    pub rr_max_probability: f32,
+    /// Trace one wavelength per sample instead of RGB, so that dispersive
+    /// dielectrics split light.
+    pub spectral: bool,
+}
+
+impl Default for PathSettings {
+    #[inline(always)]
+    fn default() -> PathSettings {
+        PathSettings {
+            max_depth: 50,
+            rr_depth: 5,
+            rr_max_probability: 0.95,
+            spectral: false,
+        }
+    }
+}
+
+impl PathSettings {
+    /// Plays Russian roulette after bounce `depth`: returns false when the
+    /// path is terminated, and otherwise divides `ratio` by the survival
+    /// probability so that the estimate stays unbiased.
+    #[inline(always)]
+    pub fn survives(&self, depth: u32, xorshift: &mut XorShift, ratio: &mut Vec3) -> bool {
+        if depth + 1 < self.rr_depth {
+            return true;
+        }
+        let max = ratio.x.max(ratio.y).max(ratio.z);
+        let q = max.min(self.rr_max_probability);
+        if !(q > 0.0) || xorshift.gen_f32() >= q {
+            return false;
+        }
+        *ratio /= q;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::*;
+
+    #[test]
+    fn paths_survive_before_rr_depth() {
+        let settings = PathSettings::default();
+        let mut xorshift = XorShift::new(1);
+        for depth in 0..settings.rr_depth - 1 {
+            let mut ratio = Vec3{x: 0.01, y: 0.0, z: 0.0};
+            for _ in 0..1000 {
+                assert!(settings.survives(depth, &mut xorshift, &mut ratio));
+            }
+            assert!(ratio.x == 0.01 && ratio.y == 0.0 && ratio.z == 0.0);
+        }
+    }
+
+    #[test]
+    fn survivors_are_weighted_by_the_inverse_probability() {
+        let settings = PathSettings::default();
+        let depth = settings.rr_depth;
+        let mut xorshift = XorShift::new(1);
+        let n = 100000;
+        let mut survived = 0;
+        for _ in 0..n {
+            let mut ratio = Vec3{x: 0.5, y: 0.25, z: 0.1};
+            if settings.survives(depth, &mut xorshift, &mut ratio) {
+                survived += 1;
+                assert!(ratio.x == 1.0 && ratio.y == 0.5 && ratio.z == 0.2);
+            }
+        }
+        let p = survived as f32 / n as f32;
+        assert!(abs(p - 0.5) < 0.01, "survival probability {}", p);
+
+        // The probability is capped, so bright paths still get terminated.
+        let mut terminated = 0;
+        for _ in 0..n {
+            let mut ratio = Vec3{x: 4.0, y: 0.0, z: 0.0};
+            if settings.survives(depth, &mut xorshift, &mut ratio) {
+                assert!(ratio.x == 4.0 / settings.rr_max_probability);
+            }
+            else {
+                terminated += 1;
+            }
+        }
+        assert!(terminated > 0);
+
+        let mut black = Vec3::new();
+        assert!(!settings.survives(depth, &mut xorshift, &mut black));
+    }
+}
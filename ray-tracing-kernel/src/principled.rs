@@ -0,0 +1,181 @@
+use crate::vec3::*;
+use crate::math::*;
+use crate::onb::*;
+use crate::xorshift::*;
+use crate::texture::*;
+use crate::microfacet::*;
+use crate::normal_map::*;
+
+/// Principled material after Burley's, every parameter but `base_color` in
+/// [0, 1]. An opaque part layers a clearcoat over a GGX specular lobe and a
+/// diffuse base, and `transmission` blends it with a rough glass of the same
+/// roughness.
+#[derive(Clone,Copy)]
+pub struct Principled {
+    pub base_color: Texture,
+    /// Blends the dielectric base towards a conductor reflecting `base_color`.
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Normal incidence reflectance of the dielectric base is 0.08 times
+    /// this, so 0.5 gives the 4% of glass (an index of refraction of 1.5).
+    pub specular: f32,
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+    pub transmission: f32,
+    /// Whitening of the diffuse base at grazing angles, as cloth shows.
+    pub sheen: f32,
+    pub normal_map: NormalMap,
+}
+
+#[inline(always)]
+fn schlick_weight(cosine: f32) -> f32 {
+    let m = (1.0 - cosine).max(0.0).min(1.0);
+    let m2 = m * m;
+    m2 * m2 * m
+}
+
+#[inline(always)]
+fn gray(c: f32) -> Vec3 {
+    Vec3{x: c, y: c, z: c}
+}
+
+impl Principled {
+    #[inline(always)]
+    fn glass_weight(&self) -> f32 {
+        (1.0 - self.metallic) * self.transmission
+    }
+
+    #[inline(always)]
+    fn dielectric_f0(&self) -> f32 {
+        0.08 * self.specular
+    }
+
+    /// Kept away from 1, where `specular = 0` would put it and the rough
+    /// dielectric's half vector divides by zero.
+    #[inline(always)]
+    fn ref_idx(&self) -> f32 {
+        let s = sqrt(self.dielectric_f0());
+        ((1.0 + s) / (1.0 - s)).max(1.0 + 1e-4)
+    }
+
+    /// Probabilities of sampling the diffuse, specular and clearcoat lobes.
+    #[inline(always)]
+    fn lobe_probabilities(&self) -> (f32, f32, f32) {
+        let diffuse = 1.0 - self.metallic;
+        let clearcoat = 0.5 * self.clearcoat;
+        let sum = diffuse + 1.0 + clearcoat;
+        (diffuse / sum, 1.0 / sum, clearcoat / sum)
+    }
+
+    /// Frame around the normal turned towards `wo`: the opaque part reflects
+    /// on either side.
+    #[inline(always)]
+    fn frame(normal: Vec3, wo: Vec3) -> Onb {
+        Onb::from_w(if Vec3::dot(wo, normal) < 0.0 { -normal } else { normal })
+    }
+
+    #[inline(always)]
+    fn eval_local(&self, base_color: Vec3, wo: Vec3, wi: Vec3) -> Vec3 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Vec3::new();
+        }
+        let h = Vec3::unit_vector(wo + wi);
+        let cos_d = Vec3::dot(wi, h);
+        let fd0 = self.dielectric_f0();
+
+        let specular_alpha = alpha(self.roughness);
+        let f0 = (1.0 - self.metallic) * gray(fd0) + self.metallic * base_color;
+        let fs = f0 + (gray(1.0) - f0) * schlick_weight(cos_d);
+        let specular = fs * (ggx_d(h, specular_alpha) * smith_g1(wo, specular_alpha) * smith_g1(wi, specular_alpha) / (4.0 * wo.z));
+
+        // The base only receives what the dielectric interface above it lets
+        // through, on the way in and out.
+        let entering = (1.0 - fd0) * (1.0 - schlick_weight(wo.z));
+        let leaving = (1.0 - fd0) * (1.0 - schlick_weight(wi.z));
+        let w = schlick_weight(cos_d);
+        let diffuse_color = (1.0 - self.sheen * w) * base_color + gray(self.sheen * w);
+        let diffuse = ((1.0 - self.metallic) * entering * leaving * wi.z / PI) * diffuse_color;
+
+        let coat_alpha = alpha(self.clearcoat_roughness);
+        let fc = 0.04 + 0.96 * schlick_weight(cos_d);
+        let clearcoat = self.clearcoat * fc * ggx_d(h, coat_alpha) * smith_g1(wo, coat_alpha) * smith_g1(wi, coat_alpha) / (4.0 * wo.z);
+        let coat_transmission = 1.0 - self.clearcoat * (0.04 + 0.96 * schlick_weight(wo.z));
+
+        (1.0 - self.glass_weight()) * (coat_transmission * (diffuse + specular) + gray(clearcoat))
+    }
+
+    #[inline(always)]
+    fn pdf_local(&self, wo: Vec3, wi: Vec3) -> f32 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let (p_diffuse, p_specular, p_clearcoat) = self.lobe_probabilities();
+        let pdf = p_diffuse * wi.z / PI
+            + p_specular * ggx_reflection_pdf(wo, wi, alpha(self.roughness))
+            + p_clearcoat * ggx_reflection_pdf(wo, wi, alpha(self.clearcoat_roughness));
+        (1.0 - self.glass_weight()) * pdf
+    }
+
+    /// The glass part's BSDF times cosine, tinted by `base_color` when
+    /// refracting, and its density.
+    #[inline(always)]
+    fn glass_local(&self, base_color: Vec3, wo: Vec3, wi: Vec3, eta: f32) -> (Vec3, f32) {
+        let glass = self.glass_weight();
+        if glass == 0.0 {
+            return (Vec3::new(), 0.0);
+        }
+        let (f, pdf) = rough_dielectric_eval(wo, wi, eta, alpha(self.roughness));
+        let tint = if wi.z < 0.0 { base_color } else { gray(1.0) };
+        ((glass * f) * tint, glass * pdf)
+    }
+
+    /// Local frame and the relative index of refraction of the glass part.
+    #[inline(always)]
+    fn local(&self, normal: Vec3, wo: Vec3) -> (Onb, f32) {
+        let eta = if Vec3::dot(wo, normal) < 0.0 { 1.0 / self.ref_idx() } else { self.ref_idx() };
+        (Principled::frame(normal, wo), eta)
+    }
+
+    /// BSDF times cosine for light arriving from `wi` and leaving towards
+    /// `wo`, both unit world space directions.
+    #[inline(always)]
+    pub fn eval(&self, base_color: Vec3, normal: Vec3, wo: Vec3, wi: Vec3) -> Vec3 {
+        let (onb, eta) = self.local(normal, wo);
+        let wo = onb.to_local(wo);
+        let wi = onb.to_local(wi);
+        self.eval_local(base_color, wo, wi) + self.glass_local(base_color, wo, wi, eta).0
+    }
+
+    /// Density `sample` produces `wi` with.
+    #[inline(always)]
+    pub fn pdf(&self, normal: Vec3, wo: Vec3, wi: Vec3) -> f32 {
+        let (onb, eta) = self.local(normal, wo);
+        let wo = onb.to_local(wo);
+        let wi = onb.to_local(wi);
+        self.pdf_local(wo, wi) + self.glass_local(Vec3::new(), wo, wi, eta).1
+    }
+
+    /// A direction `wi` drawn with density `pdf`, the opaque and glass parts
+    /// being chosen by their weights.
+    #[inline(always)]
+    pub fn sample(&self, xorshift: &mut XorShift, normal: Vec3, wo: Vec3) -> Option<Vec3> {
+        let wi = if xorshift.gen_f32() < self.glass_weight() {
+            sample_rough_dielectric(xorshift, normal, wo, self.ref_idx(), alpha(self.roughness))?.1
+        }
+        else {
+            let onb = Principled::frame(normal, wo);
+            let wo = onb.to_local(wo);
+            let (p_diffuse, p_specular, _) = self.lobe_probabilities();
+            let lobe = xorshift.gen_f32();
+            let wi = if lobe < p_diffuse {
+                random_cosine_direction(xorshift)
+            }
+            else {
+                let alpha = if lobe < p_diffuse + p_specular { alpha(self.roughness) } else { alpha(self.clearcoat_roughness) };
+                reflect_about(wo, sample_visible_normal(wo, alpha, xorshift.gen_f32(), xorshift.gen_f32()))
+            };
+            onb.local(wi)
+        };
+        Some(wi)
+    }
+}
@@ -5,6 +5,8 @@ use crate::vec3::*;
 pub struct Ray {
     pub a: Vec3,
     pub d: Vec3,
+    /// Instant within the camera shutter interval at which the ray is cast.
+    pub time: f32,
 }
 
 impl Ray {
@@ -13,12 +15,18 @@ impl Ray {
         Ray {
             a: Vec3::new(),
             d: Vec3::new(),
+            time: 0.0,
         }
     }
 
     #[inline(always)]
     pub fn new_from_origin_and_direction(a: Vec3, d: Vec3) -> Ray {
-        Ray { a, d }
+        Ray { a, d, time: 0.0 }
+    }
+
+    #[inline(always)]
+    pub fn new_from_origin_direction_and_time(a: Vec3, d: Vec3, time: f32) -> Ray {
+        Ray { a, d, time }
     }
 
     #[inline(always)]
@@ -31,6 +39,11 @@ impl Ray {
         self.d
     }
 
+    #[inline(always)]
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
     #[inline(always)]
     pub fn point_at_parameter(&self, t: f32) -> Vec3 {
         self.a + t * self.d
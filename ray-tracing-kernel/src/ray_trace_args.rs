@@ -1,6 +1,14 @@
 use crate::vec3::*;
 use crate::object::*;
 use crate::camera::*;
+use crate::scene::*;
+use crate::bvh::*;
+use crate::mesh::*;
+use crate::background::*;
+use crate::matrix::*;
+use crate::medium::*;
+use crate::light::*;
+use crate::path::*;
 use cuda_tools::cuda_slice::*;
 use core::cell::UnsafeCell;
 
@@ -11,6 +19,73 @@ pub struct RayTraceArgs<'a> {
     pub w: usize,
     pub objects_len: usize,
     pub objects: CUDASlice<'a, Object>,
+    pub bvh_len: usize,
+    pub bvh: CUDASlice<'a, BvhNode>,
+    pub bounded_len: usize,
+    pub vertices_len: usize,
+    pub vertices: CUDASlice<'a, Vec3>,
+    pub normals_len: usize,
+    pub normals: CUDASlice<'a, Vec3>,
+    pub texcoords_len: usize,
+    pub texcoords: CUDASlice<'a, [f32; 2]>,
+    pub indices_len: usize,
+    pub indices: CUDASlice<'a, [u32; 3]>,
+    pub prototypes_len: usize,
+    pub prototypes: CUDASlice<'a, ObjectShape>,
+    pub prototype_bvh_len: usize,
+    pub prototype_bvh: CUDASlice<'a, BvhNode>,
+    pub transforms_len: usize,
+    pub transforms: CUDASlice<'a, Transform>,
+    pub media_len: usize,
+    pub media: CUDASlice<'a, Medium>,
+    pub fog: Fog,
+    pub lights_len: usize,
+    pub lights: CUDASlice<'a, Light>,
+    pub texels_len: usize,
+    pub texels: CUDASlice<'a, Vec3>,
+    pub background: Background,
+    pub environment_len: usize,
+    pub environment: CUDASlice<'a, Vec3>,
     pub ray_per_pixel: usize,
     pub camera: Camera,
+    pub path: PathSettings,
+}
+
+#[inline(always)]
+fn as_slice<'a, T>(slice: &'a CUDASlice<T>, len: usize) -> &'a [T] {
+    let len = if len < slice.len() { len } else { slice.len() };
+    if len == 0 {
+        &[]
+    }
+    else {
+        unsafe { core::slice::from_raw_parts(&slice[0] as *const T, len) }
+    }
+}
+
+impl<'a> RayTraceArgs<'a> {
+    #[inline(always)]
+    pub fn scene(&self) -> Scene {
+        Scene {
+            objects: as_slice(&self.objects, self.objects_len),
+            bvh: as_slice(&self.bvh, self.bvh_len),
+            bounded_len: self.bounded_len,
+            mesh: MeshBuffers {
+                vertices: as_slice(&self.vertices, self.vertices_len),
+                normals: as_slice(&self.normals, self.normals_len),
+                texcoords: as_slice(&self.texcoords, self.texcoords_len),
+                indices: as_slice(&self.indices, self.indices_len),
+            },
+            prototypes: as_slice(&self.prototypes, self.prototypes_len),
+            prototype_bvh: as_slice(&self.prototype_bvh, self.prototype_bvh_len),
+            transforms: as_slice(&self.transforms, self.transforms_len),
+            media: as_slice(&self.media, self.media_len),
+            fog: self.fog,
+            lights: as_slice(&self.lights, self.lights_len),
+            texels: as_slice(&self.texels, self.texels_len),
+            background: self.background,
+            environment: as_slice(&self.environment, self.environment_len),
+            camera: self.camera,
+            path: self.path,
+        }
+    }
 }
@@ -0,0 +1,40 @@
+use crate::object::*;
+use crate::camera::*;
+use crate::bvh::*;
+use crate::mesh::*;
+use crate::background::*;
+use crate::vec3::*;
+use crate::matrix::*;
+use crate::medium::*;
+use crate::light::*;
+use crate::path::*;
+
+/// Everything `color` and `hit` read while tracing, borrowed as plain slices so
+/// the same code runs inside the CUDA kernel and on the host.
+#[derive(Clone,Copy)]
+pub struct Scene<'a> {
+    pub objects: &'a [Object],
+    /// BVH over `objects[..bounded_len]`. When empty every object is tested
+    /// against every ray.
+    pub bvh: &'a [BvhNode],
+    /// Objects past this index have no finite bounds and are tested against
+    /// every ray.
+    pub bounded_len: usize,
+    pub mesh: MeshBuffers<'a>,
+    /// Shapes instanced by `ObjectShape::Instance`, in the leaf order of
+    /// `prototype_bvh`, which holds one tree per prototype.
+    pub prototypes: &'a [ObjectShape],
+    pub prototype_bvh: &'a [BvhNode],
+    pub transforms: &'a [Transform],
+    pub media: &'a [Medium],
+    /// Texels of every `Texture::Image`.
+    pub texels: &'a [Vec3],
+    pub fog: Fog,
+    /// Emitters sampled directly by `color`.
+    pub lights: &'a [Light],
+    pub background: Background,
+    /// Texels of `Background::EnvironmentMap`.
+    pub environment: &'a [Vec3],
+    pub camera: Camera,
+    pub path: PathSettings,
+}
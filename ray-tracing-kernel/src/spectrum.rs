@@ -0,0 +1,161 @@
+use crate::vec3::*;
+use crate::math::*;
+use crate::xorshift::*;
+
+/// Wavelengths in nanometres that spectral rendering samples uniformly.
+pub const LAMBDA_MIN: f32 = 380.0;
+pub const LAMBDA_MAX: f32 = 720.0;
+
+/// Wavelength of the sodium D line, at which RGB rendering evaluates
+/// dispersive indices of refraction.
+pub const LAMBDA_D: f32 = 589.3;
+
+/// Index of refraction as a function of wavelength.
+#[derive(Clone,Copy)]
+pub enum Ior {
+    /// `a + b / lambda^2`, lambda in micrometres.
+    Cauchy {
+        a: f32,
+        b: f32,
+    },
+    /// `n^2 = 1 + sum b_i lambda^2 / (lambda^2 - c_i)`, lambda in micrometres.
+    Sellmeier {
+        b: [f32; 3],
+        c: [f32; 3],
+    },
+}
+
+impl Ior {
+    #[inline(always)]
+    pub fn at(&self, lambda: f32) -> f32 {
+        let l = lambda * 1e-3;
+        let l2 = l * l;
+        match *self {
+            Ior::Cauchy{a,b} => a + b / l2,
+            Ior::Sellmeier{b,c} => {
+                sqrt(1.0 + b[0] * l2 / (l2 - c[0]) + b[1] * l2 / (l2 - c[1]) + b[2] * l2 / (l2 - c[2]))
+            }
+        }
+    }
+}
+
+#[inline(always)]
+pub fn sample_wavelength(xorshift: &mut XorShift) -> f32 {
+    LAMBDA_MIN + (LAMBDA_MAX - LAMBDA_MIN) * xorshift.gen_f32()
+}
+
+// Smits' spectra for RGB to spectrum conversion, in ten equal bins over
+// [LAMBDA_MIN, LAMBDA_MAX].
+const WHITE: [f32; 10] = [1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000];
+const CYAN: [f32; 10] = [0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000];
+const MAGENTA: [f32; 10] = [1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959];
+const YELLOW: [f32; 10] = [0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840];
+const RED: [f32; 10] = [0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149];
+const GREEN: [f32; 10] = [0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025];
+const BLUE: [f32; 10] = [1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496];
+
+/// Value at `lambda` of a smooth spectrum whose color is the linear RGB `c`
+/// (Smits 1999). Linear in `c`, so emission above 1 works too.
+#[inline(always)]
+pub fn rgb_to_spectrum(c: Vec3, lambda: f32) -> f32 {
+    let bin = floor((lambda - LAMBDA_MIN) / (LAMBDA_MAX - LAMBDA_MIN) * 10.0);
+    let i = if bin < 0.0 { 0 } else if bin > 9.0 { 9 } else { bin as usize };
+    let (r, g, b) = (c.x, c.y, c.z);
+    if r <= g && r <= b {
+        r * WHITE[i] + if g <= b { (g - r) * CYAN[i] + (b - g) * BLUE[i] } else { (b - r) * CYAN[i] + (g - b) * GREEN[i] }
+    }
+    else if g <= r && g <= b {
+        g * WHITE[i] + if r <= b { (r - g) * MAGENTA[i] + (b - r) * BLUE[i] } else { (b - g) * MAGENTA[i] + (r - b) * RED[i] }
+    }
+    else {
+        b * WHITE[i] + if r <= g { (r - b) * YELLOW[i] + (g - r) * GREEN[i] } else { (g - b) * YELLOW[i] + (r - g) * RED[i] }
+    }
+}
+
+#[inline(always)]
+fn lobe(lambda: f32, mu: f32, sigma1: f32, sigma2: f32) -> f32 {
+    let t = (lambda - mu) / if lambda < mu { sigma1 } else { sigma2 };
+    exp(-0.5 * t * t)
+}
+
+/// CIE 1931 color matching functions, in the multi-lobe fit of Wyman, Sloan
+/// and Shirley 2013.
+#[inline(always)]
+pub fn cie_xyz(lambda: f32) -> Vec3 {
+    Vec3{
+        x: 1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7) - 0.065 * lobe(lambda, 501.1, 20.4, 26.2),
+        y: 0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1),
+        z: 1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8),
+    }
+}
+
+#[inline(always)]
+fn xyz_to_linear_srgb(c: Vec3) -> Vec3 {
+    Vec3{
+        x: 3.2404542 * c.x - 1.5371385 * c.y - 0.4985314 * c.z,
+        y: -0.9692660 * c.x + 1.8760108 * c.y + 0.0415560 * c.z,
+        z: 0.0556434 * c.x - 0.2040259 * c.y + 1.0572252 * c.z,
+    }
+}
+
+/// Linear sRGB of the constant spectrum 1 over [LAMBDA_MIN, LAMBDA_MAX],
+/// which `spectrum_to_rgb` maps to white.
+const WHITE_RGB: Vec3 = Vec3{x: 128.35908, y: 101.52752, z: 97.06616};
+
+/// Linear RGB estimate of the spectrum whose value `value` at the uniformly
+/// sampled `lambda` is known.
+#[inline(always)]
+pub fn spectrum_to_rgb(value: f32, lambda: f32) -> Vec3 {
+    let rgb = xyz_to_linear_srgb((value * (LAMBDA_MAX - LAMBDA_MIN)) * cie_xyz(lambda));
+    Vec3{x: rgb.x / WHITE_RGB.x, y: rgb.y / WHITE_RGB.y, z: rgb.z / WHITE_RGB.z}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mean of `spectrum_to_rgb` over evenly spaced wavelengths, the estimate
+    /// uniformly sampled wavelengths converge to.
+    fn to_rgb<F: Fn(f32) -> f32>(spectrum: F) -> Vec3 {
+        let n = 3400;
+        let mut sum = Vec3::new();
+        for i in 0..n {
+            let lambda = LAMBDA_MIN + (LAMBDA_MAX - LAMBDA_MIN) * (i as f32 + 0.5) / n as f32;
+            sum += spectrum_to_rgb(spectrum(lambda), lambda);
+        }
+        (1.0 / n as f32) * sum
+    }
+
+    fn check(a: Vec3, e: Vec3, tolerance: f32) {
+        assert!(abs(a.x - e.x) < tolerance && abs(a.y - e.y) < tolerance && abs(a.z - e.z) < tolerance, "{} {} {} != {} {} {}", a.x, a.y, a.z, e.x, e.y, e.z);
+    }
+
+    #[test]
+    fn flat_spectrum_is_white() {
+        check(to_rgb(|_| 1.0), Vec3{x: 1.0, y: 1.0, z: 1.0}, 1e-3);
+        check(to_rgb(|_| 2.5), Vec3{x: 2.5, y: 2.5, z: 2.5}, 3e-3);
+    }
+
+    #[test]
+    fn smits_spectra_round_trip() {
+        let colors = [
+            Vec3{x: 1.0, y: 1.0, z: 1.0},
+            Vec3{x: 0.5, y: 0.5, z: 0.5},
+            Vec3{x: 1.0, y: 0.0, z: 0.0},
+            Vec3{x: 0.0, y: 1.0, z: 0.0},
+            Vec3{x: 0.0, y: 0.0, z: 1.0},
+            Vec3{x: 0.1, y: 0.2, z: 0.5},
+            Vec3{x: 0.8, y: 0.6, z: 0.2},
+        ];
+        for &c in &colors {
+            check(to_rgb(|lambda| rgb_to_spectrum(c, lambda)), c, 0.02);
+        }
+    }
+
+    #[test]
+    fn sellmeier_ior_matches_bk7() {
+        let bk7 = Ior::Sellmeier{b: [1.03961212, 0.231792344, 1.01046945], c: [0.00600069867, 0.0200179144, 103.560653]};
+        assert!(abs(bk7.at(LAMBDA_D) - 1.5168) < 1e-3);
+        assert!(bk7.at(450.0) > bk7.at(650.0));
+    }
+}
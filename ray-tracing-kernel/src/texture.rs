@@ -0,0 +1,148 @@
+use crate::vec3::*;
+use crate::math::*;
+
+/// Spatially varying color, evaluated at the surface coordinates (u, v) and
+/// the position p of a hit.
+#[derive(Clone,Copy)]
+pub enum Texture {
+    Solid {
+        color: Vec3,
+    },
+    /// 3D checkerboard of cells `1 / scale` wide.
+    Checker {
+        odd: Vec3,
+        even: Vec3,
+        scale: f32,
+    },
+    /// Perlin noise of frequency `scale`, mapped to [0, 1] and tinting `color`.
+    Noise {
+        color: Vec3,
+        scale: f32,
+    },
+    /// Sum of `depth` octaves of absolute Perlin noise, tinting `color`.
+    Turbulence {
+        color: Vec3,
+        scale: f32,
+        depth: u32,
+    },
+    /// `width * height` texels of the shared texel buffer from `offset` on,
+    /// top row first, sampled with bilinear filtering. v = 0 is the bottom row.
+    Image {
+        offset: u32,
+        width: u32,
+        height: u32,
+    },
+}
+
+impl Texture {
+    #[inline(always)]
+    pub fn solid(color: Vec3) -> Texture {
+        Texture::Solid { color }
+    }
+
+    #[inline(always)]
+    pub fn value(&self, texels: &[Vec3], u: f32, v: f32, p: Vec3) -> Vec3 {
+        match *self {
+            Texture::Solid{color} => color,
+            Texture::Checker{odd,even,scale} => {
+                let sines = sin(scale * p.x) * sin(scale * p.y) * sin(scale * p.z);
+                if sines < 0.0 { odd } else { even }
+            }
+            Texture::Noise{color,scale} => 0.5 * (1.0 + perlin(scale * p)) * color,
+            Texture::Turbulence{color,scale,depth} => turbulence(scale * p, depth) * color,
+            Texture::Image{offset,width,height} => {
+                let (offset, width, height) = (offset as usize, width as usize, height as usize);
+                if width == 0 || height == 0 || offset + width * height > texels.len() {
+                    return Vec3::new();
+                }
+                let u = u - floor(u);
+                let v = if v < 0.0 { 0.0 } else if v > 1.0 { 1.0 } else { v };
+                bilinear(&texels[offset..offset + width * height], width, height, u, 1.0 - v)
+            }
+        }
+    }
+}
+
+/// Samples an image at (u, v) in [0, 1]^2, v = 0 being the top row, wrapping
+/// horizontally and clamping vertically.
+#[inline(always)]
+pub fn bilinear(texels: &[Vec3], width: usize, height: usize, u: f32, v: f32) -> Vec3 {
+    let x = u * width as f32 - 0.5;
+    let y = v * height as f32 - 0.5;
+    let x0 = floor(x);
+    let y0 = floor(y);
+    let fx = x - x0;
+    let fy = y - y0;
+    let wrap = |x: isize| -> usize {
+        let w = width as isize;
+        (((x % w) + w) % w) as usize
+    };
+    let clamp = |y: isize| -> usize {
+        if y < 0 { 0 } else if y as usize >= height { height - 1 } else { y as usize }
+    };
+    let (x0, x1) = (wrap(x0 as isize), wrap(x0 as isize + 1));
+    let (y0, y1) = (clamp(y0 as isize), clamp(y0 as isize + 1));
+    let c00 = texels[y0 * width + x0];
+    let c10 = texels[y0 * width + x1];
+    let c01 = texels[y1 * width + x0];
+    let c11 = texels[y1 * width + x1];
+    (1.0 - fy) * ((1.0 - fx) * c00 + fx * c10) + fy * ((1.0 - fx) * c01 + fx * c11)
+}
+
+/// Hashes a lattice point. Gradients come from this hash instead of the
+/// usual permutation tables so that nothing has to be uploaded.
+#[inline(always)]
+fn hash(x: i32, y: i32, z: i32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343) ^ (y as u32).wrapping_mul(0xd816_3841) ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^ (h >> 15)
+}
+
+/// Dot product of (x, y, z) with one of the 12 cube edge directions.
+#[inline(always)]
+fn grad(hash: u32, x: f32, y: f32, z: f32) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+#[inline(always)]
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+#[inline(always)]
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    a + t * (b - a)
+}
+
+/// Improved Perlin noise, roughly in [-1, 1].
+#[inline(always)]
+pub fn perlin(p: Vec3) -> f32 {
+    let (fx, fy, fz) = (floor(p.x), floor(p.y), floor(p.z));
+    let (x, y, z) = (fx as i32, fy as i32, fz as i32);
+    let (dx, dy, dz) = (p.x - fx, p.y - fy, p.z - fz);
+    let (u, v, w) = (fade(dx), fade(dy), fade(dz));
+    lerp(w,
+        lerp(v,
+            lerp(u, grad(hash(x, y, z), dx, dy, dz), grad(hash(x + 1, y, z), dx - 1.0, dy, dz)),
+            lerp(u, grad(hash(x, y + 1, z), dx, dy - 1.0, dz), grad(hash(x + 1, y + 1, z), dx - 1.0, dy - 1.0, dz))),
+        lerp(v,
+            lerp(u, grad(hash(x, y, z + 1), dx, dy, dz - 1.0), grad(hash(x + 1, y, z + 1), dx - 1.0, dy, dz - 1.0)),
+            lerp(u, grad(hash(x, y + 1, z + 1), dx, dy - 1.0, dz - 1.0), grad(hash(x + 1, y + 1, z + 1), dx - 1.0, dy - 1.0, dz - 1.0))))
+}
+
+#[inline(always)]
+pub fn turbulence(p: Vec3, depth: u32) -> f32 {
+    let mut accum = 0.0;
+    let mut p = p;
+    let mut weight = 1.0;
+    for _ in 0..depth {
+        accum += weight * abs(perlin(p));
+        weight *= 0.5;
+        p = 2.0 * p;
+    }
+    accum
+}
@@ -0,0 +1,72 @@
+//! What rays leaving the scene see.
+
+use ray_tracing_kernel as kernel;
+
+use kernel::vec3::*;
+
+use crate::hdr_image::*;
+
+#[derive(Clone)]
+pub enum Background {
+    Constant(Vec3),
+    Gradient {
+        bottom: Vec3,
+        top: Vec3,
+    },
+    /// Equirectangular map, top row looking up (+y).
+    Environment {
+        map: HdrImage,
+        /// Rotation about the y axis in radians.
+        rotation: f32,
+        intensity: f32,
+    },
+}
+
+impl Default for Background {
+    /// The white-to-blue sky of the original examples.
+    fn default() -> Background {
+        Background::Gradient {
+            bottom: Vec3 {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+            },
+            top: Vec3 {
+                x: 0.5,
+                y: 0.7,
+                z: 1.0,
+            },
+        }
+    }
+}
+
+impl Background {
+    /// The kernel-side description and the texels it samples.
+    pub fn kernel(&self) -> (kernel::background::Background, &[Vec3]) {
+        match self {
+            Background::Constant(color) => {
+                (kernel::background::Background::Constant { color: *color }, &[])
+            }
+            Background::Gradient { bottom, top } => (
+                kernel::background::Background::Gradient {
+                    bottom: *bottom,
+                    top: *top,
+                },
+                &[],
+            ),
+            Background::Environment {
+                map,
+                rotation,
+                intensity,
+            } => (
+                kernel::background::Background::EnvironmentMap {
+                    width: map.width,
+                    height: map.height,
+                    rotation: *rotation,
+                    intensity: *intensity,
+                },
+                &map.texels,
+            ),
+        }
+    }
+}
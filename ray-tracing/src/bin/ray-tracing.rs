@@ -1,6 +1,58 @@
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
+use ray_tracing::background::Background;
+use ray_tracing::error::Error;
+use ray_tracing::geometry::Geometry;
+use ray_tracing::output::OutputTransform;
+use ray_tracing::renderer::*;
+use ray_tracing::scene_file::SceneFile;
+use ray_tracing_kernel::medium::Fog;
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
 
-fn main() {
+fn parse_arg<T: FromStr>(matches: &ArgMatches, name: &str, default: T) -> Result<T, Error> {
+    match matches.value_of(name) {
+        Some(value) => value.parse::<T>().map_err(|_| Error::InvalidArgument {
+            name: name.to_string(),
+            value: value.to_string(),
+        }),
+        None => Ok(default),
+    }
+}
+
+fn write_ppm<W: Write>(out: &mut W, image: &Image, transform: &OutputTransform) -> io::Result<()> {
+    writeln!(out, "P3")?;
+    writeln!(out, "{} {}", image.width, image.height)?;
+    writeln!(out, "255")?;
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let v = transform.encode(image.pixel(x, y));
+            let r = (255.99 * v.x) as i32;
+            let g = (255.99 * v.y) as i32;
+            let b = (255.99 * v.z) as i32;
+            writeln!(out, "{} {} {}", r, g, b)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes linear radiance, scaled by the exposure only, as a little-endian
+/// portable float map (bottom row first).
+fn write_pfm<W: Write>(out: &mut W, image: &Image, transform: &OutputTransform) -> io::Result<()> {
+    writeln!(out, "PF")?;
+    writeln!(out, "{} {}", image.width, image.height)?;
+    writeln!(out, "-1.0")?;
+    for y in (0..image.height).rev() {
+        for x in 0..image.width {
+            let v = transform.expose(image.pixel(x, y));
+            for c in &[v.x, v.y, v.z] {
+                out.write_all(&c.to_bits().to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run() -> Result<(), Box<dyn std::error::Error>> {
     let matches = App::new("ray-tracing")
         .arg(
             Arg::with_name("seed")
@@ -26,26 +78,180 @@ fn main() {
                 .long("ray-per-pixel")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("backend")
+                .short("b")
+                .long("backend")
+                .takes_value(true)
+                .possible_values(&["cpu", "cuda", "emulator"]),
+        )
+        .arg(
+            Arg::with_name("kernel")
+                .long("kernel")
+                .takes_value(true)
+                .possible_values(&["sample", "pixel"]),
+        )
+        .arg(Arg::with_name("benchmark").long("benchmark"))
+        .arg(
+            Arg::with_name("max-depth")
+                .long("max-depth")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rr-depth")
+                .long("rr-depth")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rr-probability")
+                .long("rr-probability")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("exposure")
+                .long("exposure")
+                .takes_value(true)
+                .allow_hyphen_values(true),
+        )
+        .arg(
+            Arg::with_name("transfer")
+                .long("transfer")
+                .takes_value(true)
+                .value_name("linear|srgb|gamma"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .takes_value(true)
+                .possible_values(&["ppm", "pfm"]),
+        )
+        .arg(Arg::with_name("spectral").long("spectral"))
+        .arg(Arg::with_name("motion-blur").long("motion-blur"))
+        .arg(
+            Arg::with_name("scene")
+                .long("scene")
+                .takes_value(true)
+                .value_name("path"),
+        )
         .get_matches();
-    let default_seed = 0;
-    let seed = matches
-        .value_of("seed")
-        .and_then(|seed| seed.parse::<u32>().ok())
-        .unwrap_or(default_seed);
-    let default_height = 400;
-    let height = matches
-        .value_of("height")
-        .and_then(|seed| seed.parse::<usize>().ok())
-        .unwrap_or(default_height);
-    let default_width = 600;
-    let width = matches
-        .value_of("width")
-        .and_then(|seed| seed.parse::<usize>().ok())
-        .unwrap_or(default_width);
-    let default_ray_per_pixel = 128;
-    let ray_per_pixel = matches
-        .value_of("ray-per-pixel")
-        .and_then(|seed| seed.parse::<usize>().ok())
-        .unwrap_or(default_ray_per_pixel);
-    ray_tracing::run(seed, height, width, ray_per_pixel);
+    let scene = match matches.value_of("scene") {
+        Some(path) => Some(SceneFile::load(path)?),
+        None => None,
+    };
+
+    let default_settings = RenderSettings::default();
+    let scene_render = scene.as_ref().map(|scene| scene.render).unwrap_or_default();
+    let seed = parse_arg(&matches, "seed", 0u32)?;
+    let height = parse_arg(
+        &matches,
+        "height",
+        scene_render.height.unwrap_or(default_settings.height),
+    )?;
+    let width = parse_arg(
+        &matches,
+        "width",
+        scene_render.width.unwrap_or(default_settings.width),
+    )?;
+    let ray_per_pixel = parse_arg(
+        &matches,
+        "ray-per-pixel",
+        scene_render
+            .ray_per_pixel
+            .unwrap_or(default_settings.ray_per_pixel),
+    )?;
+    let backend = parse_arg(&matches, "backend", default_settings.backend)?;
+    let kernel_mode = parse_arg(&matches, "kernel", default_settings.kernel_mode)?;
+    let max_depth = parse_arg(
+        &matches,
+        "max-depth",
+        scene_render.max_depth.unwrap_or(default_settings.max_depth),
+    )?;
+    let rr_depth = parse_arg(
+        &matches,
+        "rr-depth",
+        scene_render.rr_depth.unwrap_or(default_settings.rr_depth),
+    )?;
+    let rr_probability = parse_arg(
+        &matches,
+        "rr-probability",
+        scene_render
+            .rr_probability
+            .unwrap_or(default_settings.rr_probability),
+    )?;
+
+    let spectral = matches.is_present("spectral") || scene_render.spectral.unwrap_or(false);
+
+    let settings = RenderSettings {
+        height,
+        width,
+        ray_per_pixel,
+        backend,
+        kernel_mode,
+        max_depth,
+        rr_depth,
+        rr_probability,
+        spectral,
+    };
+    let default_transform = OutputTransform::default();
+    let transform = OutputTransform {
+        exposure: parse_arg(&matches, "exposure", default_transform.exposure)?,
+        transfer: parse_arg(&matches, "transfer", default_transform.transfer)?,
+    };
+    transform.validate()?;
+    let (geometry, background, fog, camera) = match scene {
+        Some(scene) => (
+            scene.geometry,
+            scene.background,
+            scene.fog,
+            scene.camera.camera(settings.aspect()),
+        ),
+        None if matches.is_present("motion-blur") => {
+            let mut camera = ray_tracing::default_camera(settings.aspect());
+            camera.time1 = 1.0;
+            (
+                Geometry::from(ray_tracing::moving_random_scene(seed)),
+                Background::default(),
+                Fog::none(),
+                camera,
+            )
+        }
+        None => (
+            Geometry::from(ray_tracing::random_scene(seed)),
+            Background::default(),
+            Fog::none(),
+            ray_tracing::default_camera(settings.aspect()),
+        ),
+    };
+    eprintln!("objects.len() = {}", geometry.objects.len());
+
+    let renderer = Renderer::new(settings)?;
+    let image = if matches.is_present("benchmark") {
+        let (image, times) = renderer.benchmark(&geometry, &background, fog, camera)?;
+        for (kernel_mode, elapsed) in times {
+            eprintln!(
+                "{:?}: {}.{:03} s",
+                kernel_mode,
+                elapsed.as_secs(),
+                elapsed.subsec_millis()
+            );
+        }
+        image
+    } else {
+        renderer.render(&geometry, &background, fog, camera)?
+    };
+
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    match matches.value_of("format") {
+        Some("pfm") => write_pfm(&mut out, &image, &transform)?,
+        _ => write_ppm(&mut out, &image, &transform)?,
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,423 @@
+//! Binned SAH BVH builder. The tree is flattened into the `BvhNode` layout the
+//! kernel traverses and the objects are reordered so that every leaf refers to
+//! a contiguous range of them. The same builder produces the per-prototype
+//! trees over the shapes of instanced prototypes.
+
+use ray_tracing_kernel as kernel;
+
+use kernel::aabb::*;
+use kernel::bvh::*;
+use kernel::mesh::*;
+use kernel::object::*;
+use kernel::vec3::*;
+
+const N_BIN: usize = 16;
+const MAX_LEAF_SIZE: usize = 4;
+const TRAVERSAL_COST: f32 = 1.0;
+const INTERSECTION_COST: f32 = 1.0;
+
+struct Item<T> {
+    bounds: Aabb,
+    centroid: Vec3,
+    object: T,
+}
+
+impl<T> Item<T> {
+    fn new(object: T, bounds: Aabb) -> Item<T> {
+        Item {
+            bounds,
+            centroid: bounds.centroid(),
+            object,
+        }
+    }
+}
+
+/// Builds a BVH over `objects`, reordering them into leaf order. `mesh` holds
+/// the buffers the `MeshTriangle`s among them index.
+///
+/// Objects without finite bounds are moved behind the others and left out of
+/// the tree; the returned count is the number of objects it covers.
+pub fn build(objects: &mut Vec<Object>, mesh: &MeshBuffers) -> (Vec<BvhNode>, usize) {
+    let (mut items, unbounded): (Vec<_>, Vec<_>) = objects
+        .iter()
+        .map(|&object| Item::new(object, object.bounding_box(mesh)))
+        .partition(|item| item.bounds.is_bounded());
+    let mut nodes = vec![];
+    if !items.is_empty() {
+        build_recursive(&mut items, 0, 0, &mut nodes);
+    }
+    let bounded_len = items.len();
+    *objects = items
+        .into_iter()
+        .chain(unbounded)
+        .map(|item| item.object)
+        .collect();
+    (nodes, bounded_len)
+}
+
+/// Appends a tree over `shapes` to `nodes` and returns its root, reordering
+/// the shapes into leaf order. `first` is the index of `shapes[0]` in the
+/// prototype buffer the leaves refer to. `shapes` must be non-empty and
+/// bounded.
+pub fn append_prototype(
+    shapes: &mut [ObjectShape],
+    first: usize,
+    mesh: &MeshBuffers,
+    nodes: &mut Vec<BvhNode>,
+) -> usize {
+    let mut items: Vec<_> = shapes
+        .iter()
+        .map(|&shape| Item::new(shape, shape.bounding_box(mesh)))
+        .collect();
+    let root = build_recursive(&mut items, first, 0, nodes);
+    for (shape, item) in shapes.iter_mut().zip(items) {
+        *shape = item.object;
+    }
+    root
+}
+
+fn bounds_of<T>(items: &[Item<T>]) -> Aabb {
+    items
+        .iter()
+        .fold(Aabb::empty(), |b, item| Aabb::surrounding(b, item.bounds))
+}
+
+fn centroid_bounds_of<T>(items: &[Item<T>]) -> Aabb {
+    items.iter().fold(Aabb::empty(), |b, item| {
+        Aabb::surrounding(
+            b,
+            Aabb {
+                min: item.centroid,
+                max: item.centroid,
+            },
+        )
+    })
+}
+
+fn bin_of<T>(item: &Item<T>, axis: usize, centroid_bounds: &Aabb) -> usize {
+    let lo = centroid_bounds.min.i(axis);
+    let extent = centroid_bounds.max.i(axis) - lo;
+    let b = ((item.centroid.i(axis) - lo) / extent * N_BIN as f32) as usize;
+    b.min(N_BIN - 1)
+}
+
+/// Moves the items for which `pred` holds to the front and returns their count.
+fn partition<T, F: Fn(&Item<T>) -> bool>(items: &mut [Item<T>], pred: F) -> usize {
+    let mut mid = 0;
+    for i in 0..items.len() {
+        if pred(&items[i]) {
+            items.swap(i, mid);
+            mid += 1;
+        }
+    }
+    mid
+}
+
+/// Returns the axis and bin of the cheapest split, with its SAH cost.
+fn find_split<T>(
+    items: &[Item<T>],
+    bounds: &Aabb,
+    centroid_bounds: &Aabb,
+) -> Option<(usize, usize, f32)> {
+    let mut best: Option<(usize, usize, f32)> = None;
+    let area = bounds.surface_area();
+    for axis in 0..3 {
+        if !(centroid_bounds.max.i(axis) > centroid_bounds.min.i(axis)) {
+            continue;
+        }
+        let mut bin_bounds = [Aabb::empty(); N_BIN];
+        let mut bin_count = [0usize; N_BIN];
+        for item in items {
+            let b = bin_of(item, axis, centroid_bounds);
+            bin_bounds[b] = Aabb::surrounding(bin_bounds[b], item.bounds);
+            bin_count[b] += 1;
+        }
+        // right_area[b] and right_count[b] describe bins b + 1 .. N_BIN.
+        let mut right_area = [0.0f32; N_BIN];
+        let mut right_count = [0usize; N_BIN];
+        let mut acc_bounds = Aabb::empty();
+        let mut acc_count = 0;
+        for b in (1..N_BIN).rev() {
+            acc_bounds = Aabb::surrounding(acc_bounds, bin_bounds[b]);
+            acc_count += bin_count[b];
+            right_area[b - 1] = acc_bounds.surface_area();
+            right_count[b - 1] = acc_count;
+        }
+        let mut acc_bounds = Aabb::empty();
+        let mut acc_count = 0;
+        for b in 0..N_BIN - 1 {
+            acc_bounds = Aabb::surrounding(acc_bounds, bin_bounds[b]);
+            acc_count += bin_count[b];
+            if acc_count == 0 || right_count[b] == 0 {
+                continue;
+            }
+            let cost = TRAVERSAL_COST
+                + INTERSECTION_COST
+                    * (acc_bounds.surface_area() * acc_count as f32
+                        + right_area[b] * right_count[b] as f32)
+                    / area;
+            if best.map_or(true, |(_, _, best_cost)| cost < best_cost) {
+                best = Some((axis, b, cost));
+            }
+        }
+    }
+    best
+}
+
+fn set_leaf(nodes: &mut Vec<BvhNode>, node_id: usize, bounds: Aabb, start: usize, count: usize) {
+    nodes[node_id] = BvhNode {
+        bounds,
+        offset: start as u32,
+        count: count as u32,
+        axis: 0,
+    };
+}
+
+fn build_recursive<T>(
+    items: &mut [Item<T>],
+    start: usize,
+    depth: usize,
+    nodes: &mut Vec<BvhNode>,
+) -> usize {
+    let node_id = nodes.len();
+    let bounds = bounds_of(items);
+    nodes.push(BvhNode {
+        bounds,
+        offset: 0,
+        count: 0,
+        axis: 0,
+    });
+    debug_assert!(depth < BVH_MAX_DEPTH);
+    let n = items.len();
+    // The traversal stack holds BVH_MAX_DEPTH far children, so a node this
+    // deep becomes a leaf however many items it holds.
+    if n == 1 || depth + 1 >= BVH_MAX_DEPTH {
+        set_leaf(nodes, node_id, bounds, start, n);
+        return node_id;
+    }
+
+    let centroid_bounds = centroid_bounds_of(items);
+    let (axis, mid) = match find_split(items, &bounds, &centroid_bounds) {
+        Some((axis, b, cost)) => {
+            if n <= MAX_LEAF_SIZE && cost >= INTERSECTION_COST * n as f32 {
+                set_leaf(nodes, node_id, bounds, start, n);
+                return node_id;
+            }
+            let mid = partition(items, |item| bin_of(item, axis, &centroid_bounds) <= b);
+            (axis, mid)
+        }
+        None => {
+            // All centroids coincide, so no split separates anything spatially.
+            if n <= MAX_LEAF_SIZE {
+                set_leaf(nodes, node_id, bounds, start, n);
+                return node_id;
+            }
+            (0, n / 2)
+        }
+    };
+
+    let (left, right) = items.split_at_mut(mid);
+    build_recursive(left, start, depth + 1, nodes);
+    let second = build_recursive(right, start + mid, depth + 1, nodes);
+    nodes[node_id] = BvhNode {
+        bounds,
+        offset: second as u32,
+        count: 0,
+        axis: axis as u32,
+    };
+    node_id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::*;
+    use kernel::background::*;
+    use kernel::kernel::{hit, hit_brute_force};
+    use kernel::medium::*;
+    use kernel::normal_map::*;
+    use kernel::path::*;
+    use kernel::ray::*;
+    use kernel::scene::*;
+    use kernel::texture::*;
+    use kernel::xorshift::*;
+
+    const N_RAY: usize = 4096;
+
+    fn vec3(x: f32, y: f32, z: f32) -> Vec3 {
+        Vec3 { x, y, z }
+    }
+
+    fn gray() -> ObjectMaterial {
+        ObjectMaterial::Lambertian {
+            albedo: Texture::solid(vec3(0.5, 0.5, 0.5)),
+            normal_map: NormalMap::None,
+        }
+    }
+
+    fn sphere(center: Vec3, radius: f32) -> Object {
+        Object {
+            shape: ObjectShape::Sphere { center, radius },
+            material: gray(),
+        }
+    }
+
+    fn scene<'a>(geometry: &'a Geometry, bvh: &'a [BvhNode], bounded_len: usize) -> Scene<'a> {
+        Scene {
+            objects: &geometry.objects,
+            bvh,
+            bounded_len,
+            mesh: geometry.mesh_buffers(),
+            prototypes: &geometry.prototypes,
+            prototype_bvh: &geometry.prototype_bvh,
+            transforms: &geometry.transforms,
+            media: &geometry.media,
+            texels: &geometry.texels,
+            fog: Fog::none(),
+            lights: &[],
+            background: Background::Constant { color: Vec3::new() },
+            environment: &[],
+            camera: crate::default_camera(1.0),
+            path: PathSettings::default(),
+        }
+    }
+
+    /// Leaf depth below `nodes[node_id]`, which is at depth 0.
+    fn depth(nodes: &[BvhNode], node_id: usize) -> usize {
+        let node = nodes[node_id];
+        if node.is_leaf() {
+            0
+        } else {
+            1 + depth(nodes, node_id + 1).max(depth(nodes, node.offset as usize))
+        }
+    }
+
+    /// Builds the BVH, checks its shape and traces rays from random points in
+    /// [-extent, extent]^3 in random directions with and without it.
+    fn check_against_brute_force(geometry: &mut Geometry, extent: f32, seed: u32) {
+        let mesh = MeshBuffers {
+            vertices: &geometry.vertices,
+            normals: &geometry.normals,
+            texcoords: &geometry.texcoords,
+            indices: &geometry.indices,
+        };
+        let (bvh, bounded_len) = build(&mut geometry.objects, &mesh);
+        assert!(depth(&bvh, 0) < BVH_MAX_DEPTH);
+        let mut covered = vec![0; bounded_len];
+        for node in bvh.iter().filter(|node| node.is_leaf()) {
+            for i in node.offset..node.offset + node.count {
+                covered[i as usize] += 1;
+            }
+        }
+        assert!(covered.iter().all(|&count| count == 1));
+
+        let scene = scene(geometry, &bvh, bounded_len);
+        let mut xorshift = XorShift::new(seed);
+        let mut symmetric = || extent * (2.0 * xorshift.gen_f32() - 1.0);
+        let mut n_hit = 0;
+        for i in 0..N_RAY {
+            let origin = vec3(symmetric(), symmetric(), symmetric());
+            let direction = vec3(symmetric(), symmetric(), symmetric());
+            let ray = Ray::new_from_origin_and_direction(origin, direction);
+            match (
+                hit(&scene, ray, 1e-3, std::f32::MAX),
+                hit_brute_force(&scene, ray, 1e-3, std::f32::MAX),
+            ) {
+                (Some(a), Some(b)) => {
+                    assert_eq!(a.t, b.t, "ray {}", i);
+                    assert_eq!(a.object_id, b.object_id, "ray {}", i);
+                    n_hit += 1;
+                }
+                (None, None) => {}
+                (a, b) => panic!(
+                    "ray {}: BVH hit {}, brute force hit {}",
+                    i,
+                    a.is_some(),
+                    b.is_some()
+                ),
+            }
+        }
+        // Rays that all miss would not test anything.
+        assert!(n_hit > N_RAY / 10);
+    }
+
+    #[test]
+    fn random_scene_matches_brute_force() {
+        let mut geometry = Geometry::from(crate::random_scene(0));
+        check_against_brute_force(&mut geometry, 12.0, 1);
+    }
+
+    #[test]
+    fn mesh_matches_brute_force() {
+        // A wavy height field of 2 * 24 * 24 triangles over [-2, 2]^2.
+        let n = 24;
+        let mut mesh = Mesh::default();
+        for i in 0..=n {
+            for j in 0..=n {
+                let x = 4.0 * i as f32 / n as f32 - 2.0;
+                let z = 4.0 * j as f32 / n as f32 - 2.0;
+                let y = 0.5 * (3.0 * x).sin() * z.cos();
+                mesh.vertices.push(vec3(x, y, z));
+            }
+        }
+        for i in 0..n {
+            for j in 0..n {
+                let v = (i * (n + 1) + j) as u32;
+                let row = n as u32 + 1;
+                mesh.indices.push([v, v + 1, v + row]);
+                mesh.indices.push([v + 1, v + row + 1, v + row]);
+            }
+        }
+        let mut geometry = Geometry::default();
+        geometry.add_mesh(&mesh, gray());
+        geometry.objects.push(sphere(vec3(0.0, 1.0, 0.0), 0.5));
+        check_against_brute_force(&mut geometry, 3.0, 2);
+    }
+
+    #[test]
+    fn planes_match_brute_force() {
+        let mut objects = crate::small_scene(0);
+        objects.push(Object {
+            shape: ObjectShape::Plane {
+                point: vec3(0.0, -0.5, 0.0),
+                normal: vec3(0.0, 1.0, 0.0),
+            },
+            material: gray(),
+        });
+        objects.insert(
+            0,
+            Object {
+                shape: ObjectShape::Plane {
+                    point: vec3(0.0, 0.0, -3.0),
+                    normal: vec3(0.0, 0.0, 1.0),
+                },
+                material: gray(),
+            },
+        );
+        let mut geometry = Geometry::from(objects);
+        check_against_brute_force(&mut geometry, 4.0, 3);
+        assert!(geometry.objects[geometry.objects.len() - 2..]
+            .iter()
+            .all(|object| match object.shape {
+                ObjectShape::Plane { .. } => true,
+                _ => false,
+            }));
+    }
+
+    #[test]
+    fn overlapping_scene_stays_within_max_depth() {
+        // Concentric spheres, whose centroids no split separates, and spheres
+        // crowding towards the origin so that every binned split peels off
+        // only a few of them. Radii differ so that no two hits tie.
+        let mut objects = vec![];
+        for i in 0..256 {
+            objects.push(sphere(Vec3::new(), 1.0 + 0.01 * i as f32));
+        }
+        for i in 0..120 {
+            let radius = 5.0 + 0.01 * i as f32;
+            objects.push(sphere(vec3(0.5f32.powi(i), 0.0, 0.0), radius));
+        }
+        let mut geometry = Geometry::from(objects);
+        check_against_brute_force(&mut geometry, 5.0, 4);
+    }
+}
@@ -0,0 +1,22 @@
+use rayon::prelude::*;
+use ray_tracing_kernel as kernel;
+
+use kernel::scene::*;
+use kernel::vec3::*;
+
+/// Renders on the host with the same per-sample logic as the `ray_trace` kernel.
+/// Each pixel sums its own `ray_per_pixel` samples, so the result has the same
+/// layout as the image read back from the device.
+pub fn render(scene: &Scene, h: usize, w: usize, ray_per_pixel: usize) -> Vec<Vec3> {
+    let mut image = vec![Vec3::new(); h * w];
+    if h == 0 || w == 0 || ray_per_pixel == 0 {
+        return image;
+    }
+    image.par_iter_mut().enumerate().for_each(|(p, pixel)| {
+        for i in p * ray_per_pixel..(p + 1) * ray_per_pixel {
+            let (_, res) = kernel::kernel::sample(scene, h, w, ray_per_pixel, i);
+            *pixel += res;
+        }
+    });
+    image
+}
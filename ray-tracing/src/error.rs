@@ -0,0 +1,64 @@
+use device_runtime::RuntimeError;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    DeviceInit(RuntimeError),
+    Alloc(RuntimeError),
+    Launch(RuntimeError),
+    Readback(RuntimeError),
+    InvalidSettings(String),
+    InvalidArgument { name: String, value: String },
+    Scene { path: String, line: usize, message: String },
+    Model { path: String, line: usize, message: String },
+    Image { path: String, message: String },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::DeviceInit(e) => write!(f, "failed to initialize the device: {}", e),
+            Error::Alloc(e) => write!(f, "failed to allocate device memory: {}", e),
+            Error::Launch(e) => write!(f, "failed to launch the kernel: {}", e),
+            Error::Readback(e) => write!(f, "failed to copy the result to the host: {}", e),
+            Error::InvalidSettings(message) => write!(f, "invalid render settings: {}", message),
+            Error::InvalidArgument { name, value } => {
+                write!(f, "invalid value for --{}: {:?}", name, value)
+            }
+            Error::Image { path, message }
+            | Error::Scene {
+                path,
+                line: 0,
+                message,
+            }
+            | Error::Model {
+                path,
+                line: 0,
+                message,
+            } => write!(f, "{}: {}", path, message),
+            Error::Scene {
+                path,
+                line,
+                message,
+            }
+            | Error::Model {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path, line, message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::DeviceInit(e) | Error::Alloc(e) | Error::Launch(e) | Error::Readback(e) => {
+                Some(e)
+            }
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use ray_tracing_kernel as kernel;
+
+use crate::hdr_image::*;
+
+use kernel::aabb::*;
+use kernel::bvh::*;
+use kernel::matrix::*;
+use kernel::medium::*;
+use kernel::mesh::*;
+use kernel::object::*;
+use kernel::texture::*;
+use kernel::vec3::*;
+
+/// An indexed triangle mesh. `normals` and `texcoords` are either empty or
+/// have one entry per vertex.
+#[derive(Clone, Default)]
+pub struct Mesh {
+    pub vertices: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub texcoords: Vec<[f32; 2]>,
+    pub indices: Vec<[u32; 3]>,
+}
+
+/// The six outward-facing rectangles bounding the axis-aligned box [min, max].
+pub fn box_faces(min: Vec3, max: Vec3) -> [ObjectShape; 6] {
+    let xy = |k, flip| ObjectShape::XyRect {
+        x0: min.x,
+        x1: max.x,
+        y0: min.y,
+        y1: max.y,
+        k,
+        flip,
+    };
+    let xz = |k, flip| ObjectShape::XzRect {
+        x0: min.x,
+        x1: max.x,
+        z0: min.z,
+        z1: max.z,
+        k,
+        flip,
+    };
+    let yz = |k, flip| ObjectShape::YzRect {
+        y0: min.y,
+        y1: max.y,
+        z0: min.z,
+        z1: max.z,
+        k,
+        flip,
+    };
+    [
+        xy(max.z, false),
+        xy(min.z, true),
+        xz(max.y, false),
+        xz(min.y, true),
+        yz(max.x, false),
+        yz(min.x, true),
+    ]
+}
+
+/// Handle to shapes added with `Geometry::add_prototype`, placed in the scene
+/// by `Geometry::add_instance`.
+#[derive(Clone, Copy)]
+pub struct Prototype {
+    root: u32,
+    bounds: Aabb,
+}
+
+/// The objects of a scene together with the buffers their mesh triangles index
+/// and the prototypes and transforms their instances refer to.
+#[derive(Clone, Default)]
+pub struct Geometry {
+    pub objects: Vec<Object>,
+    pub vertices: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub texcoords: Vec<[f32; 2]>,
+    pub indices: Vec<[u32; 3]>,
+    pub prototypes: Vec<ObjectShape>,
+    pub prototype_bvh: Vec<BvhNode>,
+    pub transforms: Vec<Transform>,
+    pub media: Vec<Medium>,
+    /// Texels of every `Texture::Image`.
+    pub texels: Vec<Vec3>,
+}
+
+impl From<Vec<Object>> for Geometry {
+    fn from(objects: Vec<Object>) -> Geometry {
+        Geometry {
+            objects,
+            ..Geometry::default()
+        }
+    }
+}
+
+impl Geometry {
+    /// Appends the mesh to the shared buffers and returns one `MeshTriangle` per
+    /// face, to be added as objects or as a prototype.
+    pub fn append_mesh(&mut self, mesh: &Mesh) -> Vec<ObjectShape> {
+        let smooth = mesh.normals.len() == mesh.vertices.len();
+        let textured = mesh.texcoords.len() == mesh.vertices.len();
+        // Keep `normals` and `texcoords` parallel to `vertices` so that
+        // indices address all three.
+        self.normals.resize(self.vertices.len(), Vec3::new());
+        self.texcoords.resize(self.vertices.len(), [0.0, 0.0]);
+        let base = self.vertices.len() as u32;
+        self.vertices.extend_from_slice(&mesh.vertices);
+        if smooth {
+            self.normals.extend_from_slice(&mesh.normals);
+        } else {
+            self.normals.resize(self.vertices.len(), Vec3::new());
+        }
+        if textured {
+            self.texcoords.extend_from_slice(&mesh.texcoords);
+        } else {
+            self.texcoords.resize(self.vertices.len(), [0.0, 0.0]);
+        }
+        let mut shapes = vec![];
+        for &[i0, i1, i2] in &mesh.indices {
+            let triangle = self.indices.len() as u32;
+            self.indices.push([base + i0, base + i1, base + i2]);
+            shapes.push(ObjectShape::MeshTriangle {
+                triangle,
+                smooth,
+                textured,
+            });
+        }
+        shapes
+    }
+
+    /// Appends the mesh to the shared buffers and adds one `MeshTriangle` per face.
+    pub fn add_mesh(&mut self, mesh: &Mesh, material: ObjectMaterial) {
+        for shape in self.append_mesh(mesh) {
+            self.objects.push(Object { shape, material });
+        }
+    }
+
+    /// Stores `shapes` once for any number of instances and builds their BVH.
+    /// Unbounded shapes and instances are skipped: prototypes do not nest.
+    pub fn add_prototype(&mut self, mut shapes: Vec<ObjectShape>) -> Prototype {
+        let mesh = MeshBuffers {
+            vertices: &self.vertices,
+            normals: &self.normals,
+            texcoords: &self.texcoords,
+            indices: &self.indices,
+        };
+        shapes.retain(|shape| match shape {
+            ObjectShape::Instance { .. } => false,
+            _ => shape.bounding_box(&mesh).is_bounded(),
+        });
+        if shapes.is_empty() {
+            return Prototype {
+                root: u32::max_value(),
+                bounds: Aabb::empty(),
+            };
+        }
+        let first = self.prototypes.len();
+        let root = crate::bvh::append_prototype(&mut shapes, first, &mesh, &mut self.prototype_bvh);
+        self.prototypes.extend(shapes);
+        Prototype {
+            root: root as u32,
+            bounds: self.prototype_bvh[root].bounds,
+        }
+    }
+
+    /// Places `prototype` in the scene, its surfaces made of `material`.
+    pub fn add_instance(&mut self, prototype: Prototype, transform: Transform, material: ObjectMaterial) {
+        let index = self.transforms.len() as u32;
+        self.transforms.push(transform);
+        self.objects.push(Object {
+            shape: ObjectShape::Instance {
+                root: prototype.root,
+                transform: index,
+                bounds: transform.bounding_box(prototype.bounds),
+            },
+            material,
+        });
+    }
+
+    /// Fills the closed surface made of `boundary` with a constant-density
+    /// medium scattering as `material`.
+    pub fn add_medium(&mut self, boundary: Vec<ObjectShape>, density: f32, material: ObjectMaterial) {
+        let prototype = self.add_prototype(boundary);
+        self.media.push(Medium {
+            boundary: prototype.root,
+            density,
+            material,
+        });
+    }
+
+    /// Appends the image to the shared texel buffer and returns the texture
+    /// sampling it.
+    pub fn add_image_texture(&mut self, image: &HdrImage) -> Texture {
+        let offset = self.texels.len() as u32;
+        self.texels.extend_from_slice(&image.texels);
+        Texture::Image {
+            offset,
+            width: image.width as u32,
+            height: image.height as u32,
+        }
+    }
+
+    pub fn add_box(&mut self, min: Vec3, max: Vec3, material: ObjectMaterial) {
+        for &shape in &box_faces(min, max) {
+            self.objects.push(Object { shape, material });
+        }
+    }
+
+    pub fn mesh_buffers(&self) -> MeshBuffers {
+        MeshBuffers {
+            vertices: &self.vertices,
+            normals: &self.normals,
+            texcoords: &self.texcoords,
+            indices: &self.indices,
+        }
+    }
+}
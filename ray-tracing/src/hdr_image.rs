@@ -0,0 +1,379 @@
+//! Linear float RGB images, read from Radiance RGBE (`.hdr`) and portable float
+//! map (`.pfm`) files or decoded from 8-bit formats.
+
+use ray_tracing_kernel as kernel;
+use std::fs;
+use std::path::Path;
+
+use kernel::vec3::*;
+
+use crate::error::*;
+
+/// Linear RGB texels in row-major order, top row first.
+#[derive(Clone)]
+pub struct HdrImage {
+    pub width: usize,
+    pub height: usize,
+    pub texels: Vec<Vec3>,
+}
+
+fn image_error(path: &Path, message: String) -> Error {
+    Error::Image {
+        path: path.display().to_string(),
+        message,
+    }
+}
+
+impl HdrImage {
+    /// Loads a Radiance `.hdr` or `.pfm` file, or an 8-bit image such as PNG or
+    /// JPEG whose sRGB values are converted to linear, chosen by extension.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<HdrImage> {
+        load_image(path.as_ref(), true)
+    }
+
+    /// Like `load`, but keeps the values of 8-bit images as they are, for
+    /// images holding data such as normals or heights rather than colors.
+    pub fn load_linear<P: AsRef<Path>>(path: P) -> Result<HdrImage> {
+        load_image(path.as_ref(), false)
+    }
+}
+
+fn load_image(path: &Path, srgb: bool) -> Result<HdrImage> {
+    let bytes = fs::read(path).map_err(|e| image_error(path, e.to_string()))?;
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let image = match extension.as_ref().map(|e| e.as_str()) {
+        Some("hdr") => parse_hdr(&bytes),
+        Some("pfm") => parse_pfm(&bytes),
+        _ => decode_ldr(&bytes, srgb),
+    };
+    image.map_err(|message| image_error(path, message))
+}
+
+fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn decode_ldr(bytes: &[u8], srgb: bool) -> std::result::Result<HdrImage, String> {
+    let decode = |c: u8| {
+        if srgb {
+            srgb_to_linear(c)
+        } else {
+            c as f32 / 255.0
+        }
+    };
+    let image = image::load_from_memory(bytes)
+        .map_err(|e| e.to_string())?
+        .to_rgb();
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let texels = image
+        .pixels()
+        .map(|p| Vec3 {
+            x: decode(p[0]),
+            y: decode(p[1]),
+            z: decode(p[2]),
+        })
+        .collect();
+    Ok(HdrImage {
+        width,
+        height,
+        texels,
+    })
+}
+
+/// Splits off the next `\n`-terminated line.
+fn next_line<'a>(bytes: &mut &'a [u8]) -> std::result::Result<&'a str, String> {
+    let end = bytes
+        .iter()
+        .position(|&b| b == b'\n')
+        .ok_or_else(|| "unexpected end of header".to_string())?;
+    let line = std::str::from_utf8(&bytes[..end]).map_err(|_| "header is not ASCII".to_string())?;
+    *bytes = &bytes[end + 1..];
+    Ok(line.trim_end_matches('\r'))
+}
+
+fn rgbe_to_vec3(rgbe: [u8; 4]) -> Vec3 {
+    if rgbe[3] == 0 {
+        return Vec3::new();
+    }
+    let f = 2f32.powi(rgbe[3] as i32 - 136);
+    Vec3 {
+        x: (rgbe[0] as f32 + 0.5) * f,
+        y: (rgbe[1] as f32 + 0.5) * f,
+        z: (rgbe[2] as f32 + 0.5) * f,
+    }
+}
+
+/// Reads one scanline of `width` RGBE pixels, flat or new-style run-length encoded.
+fn read_scanline(
+    bytes: &mut &[u8],
+    width: usize,
+    out: &mut Vec<[u8; 4]>,
+) -> std::result::Result<(), String> {
+    let truncated = || "pixel data is truncated".to_string();
+    let rle = width >= 8
+        && width < 0x8000
+        && bytes.len() >= 4
+        && bytes[0] == 2
+        && bytes[1] == 2
+        && bytes[2] & 0x80 == 0;
+    if !rle {
+        for _ in 0..width {
+            if bytes.len() < 4 {
+                return Err(truncated());
+            }
+            out.push([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            *bytes = &bytes[4..];
+        }
+        return Ok(());
+    }
+    if ((bytes[2] as usize) << 8 | bytes[3] as usize) != width {
+        return Err("scanline width does not match the image width".to_string());
+    }
+    *bytes = &bytes[4..];
+    let start = out.len();
+    out.resize(start + width, [0; 4]);
+    let line = &mut out[start..];
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let (&count, rest) = bytes.split_first().ok_or_else(truncated)?;
+            *bytes = rest;
+            if count > 128 {
+                let n = count as usize - 128;
+                let (&value, rest) = bytes.split_first().ok_or_else(truncated)?;
+                *bytes = rest;
+                if x + n > width {
+                    return Err("run overflows the scanline".to_string());
+                }
+                for pixel in &mut line[x..x + n] {
+                    pixel[channel] = value;
+                }
+                x += n;
+            } else {
+                let n = count as usize;
+                if n == 0 || x + n > width {
+                    return Err("run overflows the scanline".to_string());
+                }
+                if bytes.len() < n {
+                    return Err(truncated());
+                }
+                for (pixel, &value) in line[x..x + n].iter_mut().zip(&bytes[..n]) {
+                    pixel[channel] = value;
+                }
+                *bytes = &bytes[n..];
+                x += n;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn parse_hdr(mut bytes: &[u8]) -> std::result::Result<HdrImage, String> {
+    let magic = next_line(&mut bytes)?;
+    if !magic.starts_with("#?") {
+        return Err("not a Radiance HDR file".to_string());
+    }
+    loop {
+        let line = next_line(&mut bytes)?;
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+            return Err(format!("unsupported pixel format {:?}", &line[7..]));
+        }
+    }
+    let resolution = next_line(&mut bytes)?;
+    let fields: Vec<&str> = resolution.split_whitespace().collect();
+    if fields.len() != 4 || fields[2] != "+X" || !(fields[0] == "-Y" || fields[0] == "+Y") {
+        return Err(format!("unsupported orientation {:?}", resolution));
+    }
+    let (flip, height, width) = (fields[0] == "+Y", fields[1], fields[3]);
+    let parse = |s: &str| {
+        s.parse::<usize>()
+            .ok()
+            .filter(|&n| n > 0)
+            .ok_or_else(|| format!("invalid image size {:?}", resolution))
+    };
+    let (width, height) = (parse(width)?, parse(height)?);
+    // A run-length encoded run of 128 pixels takes two bytes per channel, so
+    // no valid file holds more than 16 pixels per remaining byte.
+    let pixels = width
+        .checked_mul(height)
+        .filter(|&n| n / 16 <= bytes.len())
+        .ok_or_else(|| format!("image size {:?} exceeds the file size", resolution))?;
+
+    let mut rgbe = Vec::with_capacity(pixels);
+    for _ in 0..height {
+        read_scanline(&mut bytes, width, &mut rgbe)?;
+    }
+    let mut texels: Vec<Vec3> = rgbe.into_iter().map(rgbe_to_vec3).collect();
+    if flip {
+        flip_rows(&mut texels, width);
+    }
+    Ok(HdrImage {
+        width,
+        height,
+        texels,
+    })
+}
+
+fn parse_pfm(mut bytes: &[u8]) -> std::result::Result<HdrImage, String> {
+    // The header is three whitespace-separated tokens after the magic,
+    // terminated by a single whitespace byte.
+    let mut tokens = vec![];
+    while tokens.len() < 4 {
+        while let Some((&b, rest)) = bytes.split_first() {
+            if !b.is_ascii_whitespace() {
+                break;
+            }
+            bytes = rest;
+        }
+        let end = bytes
+            .iter()
+            .position(|b| b.is_ascii_whitespace())
+            .ok_or_else(|| "unexpected end of header".to_string())?;
+        let token =
+            std::str::from_utf8(&bytes[..end]).map_err(|_| "header is not ASCII".to_string())?;
+        tokens.push(token);
+        bytes = &bytes[end + 1..];
+    }
+    let channels = match tokens[0] {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err("not a PFM file".to_string()),
+    };
+    let size = |s: &str| {
+        s.parse::<usize>()
+            .ok()
+            .filter(|&n| n > 0)
+            .ok_or_else(|| format!("invalid image size {:?}", s))
+    };
+    let width = size(tokens[1])?;
+    let height = size(tokens[2])?;
+    let scale: f32 = tokens[3]
+        .parse()
+        .map_err(|_| format!("invalid scale {:?}", tokens[3]))?;
+    let little_endian = scale < 0.0;
+
+    let n = width
+        .checked_mul(height)
+        .and_then(|n| n.checked_mul(channels * 4))
+        .ok_or_else(|| format!("image size {}x{} is too large", width, height))?
+        / 4;
+    if bytes.len() < n * 4 {
+        return Err("pixel data is truncated".to_string());
+    }
+    let values: Vec<f32> = bytes[..n * 4]
+        .chunks(4)
+        .map(|b| {
+            let b = [b[0], b[1], b[2], b[3]];
+            f32::from_bits(if little_endian {
+                u32::from_le_bytes(b)
+            } else {
+                u32::from_be_bytes(b)
+            })
+        })
+        .collect();
+    let mut texels: Vec<Vec3> = values
+        .chunks(channels)
+        .map(|c| {
+            if channels == 3 {
+                Vec3 {
+                    x: c[0],
+                    y: c[1],
+                    z: c[2],
+                }
+            } else {
+                Vec3 {
+                    x: c[0],
+                    y: c[0],
+                    z: c[0],
+                }
+            }
+        })
+        .collect();
+    // PFM rows are stored bottom to top.
+    flip_rows(&mut texels, width);
+    Ok(HdrImage {
+        width,
+        height,
+        texels,
+    })
+}
+
+fn flip_rows(texels: &mut [Vec3], width: usize) {
+    let height = texels.len() / width;
+    for y in 0..height / 2 {
+        let (top, bottom) = texels.split_at_mut((height - 1 - y) * width);
+        top[y * width..(y + 1) * width].swap_with_slice(&mut bottom[..width]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pfm(header: &str, values: &[f32]) -> Vec<u8> {
+        let mut bytes = header.as_bytes().to_vec();
+        for v in values {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        bytes
+    }
+
+    #[test]
+    fn pfm_rows_are_flipped() {
+        let image = parse_pfm(&pfm("Pf\n1 2\n-1.0\n", &[0.25, 0.75])).unwrap();
+        assert_eq!((image.width, image.height), (1, 2));
+        assert_eq!(image.texels[0].y, 0.75);
+        assert_eq!(image.texels[1].y, 0.25);
+    }
+
+    #[test]
+    fn truncated_pfm_is_rejected() {
+        let error = parse_pfm(&pfm("PF\n2 2\n-1.0\n", &[0.0; 11]))
+            .err()
+            .unwrap();
+        assert_eq!(error, "pixel data is truncated");
+    }
+
+    #[test]
+    fn oversized_pfm_header_is_rejected() {
+        let header = format!("PF\n{} {}\n-1.0\n", usize::max_value() / 2, 3);
+        let error = parse_pfm(&pfm(&header, &[0.0; 3])).err().unwrap();
+        assert!(error.contains("too large"), "{}", error);
+    }
+
+    #[test]
+    fn flat_hdr_is_decoded() {
+        let mut bytes = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 2\n".to_vec();
+        bytes.extend_from_slice(&[128, 64, 0, 129, 0, 0, 0, 0]);
+        let image = parse_hdr(&bytes).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.texels[0].x, 128.5 / 128.0);
+        assert_eq!(image.texels[1].x, 0.0);
+    }
+
+    #[test]
+    fn truncated_hdr_is_rejected() {
+        let mut bytes = b"#?RADIANCE\n\n-Y 2 +X 2\n".to_vec();
+        bytes.extend_from_slice(&[128, 64, 0, 129]);
+        let error = parse_hdr(&bytes).err().unwrap();
+        assert_eq!(error, "pixel data is truncated");
+    }
+
+    #[test]
+    fn oversized_hdr_header_is_rejected() {
+        let header = format!("#?RADIANCE\n\n-Y {} +X 4096\n", 1u64 << 40);
+        let error = parse_hdr(header.as_bytes()).err().unwrap();
+        assert!(error.contains("exceeds the file size"), "{}", error);
+    }
+}
@@ -4,14 +4,25 @@
 #[macro_use]
 extern crate cuda_tools;
 
-use core::cell::UnsafeCell;
+pub mod background;
+pub mod bvh;
+pub mod cpu;
+pub mod error;
+pub mod geometry;
+pub mod hdr_image;
+pub mod obj;
+pub mod output;
+pub mod renderer;
+pub mod scene_file;
+
 use ray_tracing_kernel as kernel;
 
 const KERNEL: &str = include_kernel!();
 
 use kernel::camera::*;
+use kernel::normal_map::*;
 use kernel::object::*;
-use kernel::ray_trace_args::*;
+use kernel::texture::*;
 use kernel::vec3::*;
 
 pub fn new_camera(
@@ -22,6 +33,8 @@ pub fn new_camera(
     aspect: f32,
     aperture: f32,
     focus_dist: f32,
+    time0: f32,
+    time1: f32,
 ) -> Camera {
     let lens_radius = aperture / 2.0;
     let theta = vfov * 3.141592653589793238f32 / 180.0;
@@ -44,10 +57,12 @@ pub fn new_camera(
         v,
         w,
         lens_radius,
+        time0,
+        time1,
     }
 }
 
-fn small_scene(seed: u32) -> Vec<Object> {
+pub fn small_scene(seed: u32) -> Vec<Object> {
     let objects = vec![
         Object {
             shape: ObjectShape::Sphere {
@@ -59,11 +74,12 @@ fn small_scene(seed: u32) -> Vec<Object> {
                 radius: 0.5,
             },
             material: ObjectMaterial::Lambertian {
-                albedo: Vec3 {
+                albedo: Texture::solid(Vec3 {
                     x: 0.1,
                     y: 0.2,
                     z: 0.5,
-                },
+                }),
+                normal_map: NormalMap::None,
             },
         },
         Object {
@@ -76,11 +92,12 @@ fn small_scene(seed: u32) -> Vec<Object> {
                 radius: 100.0,
             },
             material: ObjectMaterial::Lambertian {
-                albedo: Vec3 {
+                albedo: Texture::solid(Vec3 {
                     x: 0.8,
                     y: 0.8,
                     z: 0.0,
-                },
+                }),
+                normal_map: NormalMap::None,
             },
         },
         Object {
@@ -127,7 +144,7 @@ fn small_scene(seed: u32) -> Vec<Object> {
     objects
 }
 
-fn random_scene(seed: u32) -> Vec<Object> {
+pub fn random_scene(seed: u32) -> Vec<Object> {
     use kernel::xorshift::*;
     let mut xorshift = XorShift::new(seed);
     let mut res = vec![];
@@ -141,11 +158,12 @@ fn random_scene(seed: u32) -> Vec<Object> {
             radius: 1000.0,
         },
         material: ObjectMaterial::Lambertian {
-            albedo: Vec3 {
+            albedo: Texture::solid(Vec3 {
                 x: 0.5,
                 y: 0.5,
                 z: 0.5,
-            },
+            }),
+            normal_map: NormalMap::None,
         },
     });
     let size = 11;
@@ -173,11 +191,12 @@ fn random_scene(seed: u32) -> Vec<Object> {
                             radius: 0.2,
                         },
                         material: ObjectMaterial::Lambertian {
-                            albedo: Vec3 {
+                            albedo: Texture::solid(Vec3 {
                                 x: xorshift.gen_f32() * xorshift.gen_f32(),
                                 y: xorshift.gen_f32() * xorshift.gen_f32(),
                                 z: xorshift.gen_f32() * xorshift.gen_f32(),
-                            },
+                            }),
+                            normal_map: NormalMap::None,
                         },
                     });
                 } else if choose_mat < 0.95 {
@@ -228,11 +247,12 @@ fn random_scene(seed: u32) -> Vec<Object> {
             radius: 1.0,
         },
         material: ObjectMaterial::Lambertian {
-            albedo: Vec3 {
+            albedo: Texture::solid(Vec3 {
                 x: 0.4,
                 y: 0.2,
                 z: 0.1,
-            },
+            }),
+            normal_map: NormalMap::None,
         },
     });
     res.push(Object {
@@ -256,27 +276,41 @@ fn random_scene(seed: u32) -> Vec<Object> {
     res
 }
 
-pub fn run(seed: u32, height: usize, width: usize, ray_per_pixel: usize) {
-    let mut runtime = cuda_tools::runtime::Runtime::new(0, KERNEL).unwrap();
-    runtime.record_function_name(kernel::kernel::ray_trace, "ray_trace");
-
-    let h = height;
-    let w = width;
-    let n_thread = h * w * ray_per_pixel;
-    let n = h * w;
-
-    let mut image_h = vec![];
-    for _ in 0..n {
-        image_h.push(UnsafeCell::new(Vec3::new()));
-    }
-    let image_d = runtime.alloc_slice(&image_h).unwrap();
-
-    let m = 64;
-    let objects = random_scene(seed);
-    eprintln!("objects.len() = {}", objects.len());
-
-    let objects_d = runtime.alloc_slice(&objects).unwrap();
+/// `random_scene` with its small diffuse spheres bouncing up during the
+/// shutter interval [0, 1], to be rendered with motion blur.
+pub fn moving_random_scene(seed: u32) -> Vec<Object> {
+    use kernel::xorshift::*;
+    let mut xorshift = XorShift::new(seed ^ 0x9e37_79b9);
+    random_scene(seed)
+        .into_iter()
+        .map(|object| match (object.shape, object.material) {
+            (ObjectShape::Sphere { center, radius }, ObjectMaterial::Lambertian { .. })
+                if radius < 1.0 =>
+            {
+                let center1 = center
+                    + Vec3 {
+                        x: 0.0,
+                        y: 0.5 * xorshift.gen_f32(),
+                        z: 0.0,
+                    };
+                Object {
+                    shape: ObjectShape::MovingSphere {
+                        center0: center,
+                        center1,
+                        time0: 0.0,
+                        time1: 1.0,
+                        radius,
+                    },
+                    material: object.material,
+                }
+            }
+            _ => object,
+        })
+        .collect()
+}
 
+/// The camera the example images are rendered with, looking at `random_scene`.
+pub fn default_camera(aspect: f32) -> Camera {
     let lookfrom = Vec3 {
         x: 10.0,
         y: 2.0,
@@ -289,56 +323,19 @@ pub fn run(seed: u32, height: usize, width: usize, ray_per_pixel: usize) {
     };
     let dist_to_focus: f32 = (lookfrom - lookat).length();
     let aperture = 0.00;
-
-    let args = RayTraceArgs {
-        image_len: n,
-        image: image_d,
-        h,
-        w,
-        objects_len: objects.len(),
-        objects: objects_d,
-        ray_per_pixel,
-        camera: new_camera(
-            lookfrom,
-            lookat,
-            Vec3 {
-                x: 0.0,
-                y: 1.0,
-                z: 0.0,
-            },
-            30.0,
-            w as f32 / h as f32,
-            aperture,
-            dist_to_focus,
-        ),
-    };
-
-    runtime
-        .launch(
-            kernel::kernel::ray_trace,
-            &args,
-            (n_thread + m - 1) / m,
-            1,
-            1,
-            m,
-            1,
-            1,
-        )
-        .unwrap();
-
-    let image = args.image.to_host().unwrap();
-    let image: Vec<Vec3> = image.into_iter().map(|x| x.into_inner()).collect();
-
-    println!("P3");
-    println!("{} {}", w, h);
-    println!("255");
-    for y in 0..h {
-        for x in 0..w {
-            let v = image[y * w + x];
-            let r = (255.99 * v.x) as i32;
-            let g = (255.99 * v.y) as i32;
-            let b = (255.99 * v.z) as i32;
-            println!("{} {} {}", r, g, b);
-        }
-    }
+    new_camera(
+        lookfrom,
+        lookat,
+        Vec3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        },
+        30.0,
+        aspect,
+        aperture,
+        dist_to_focus,
+        0.0,
+        0.0,
+    )
 }
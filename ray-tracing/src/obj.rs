@@ -0,0 +1,524 @@
+//! Wavefront OBJ/MTL loader.
+//!
+//! Reads positions, normals, texture coordinates and faces (polygons are
+//! triangulated as fans, so they are assumed to be convex), together with the
+//! `Kd`, `Ks`, `Ke`, `Ns`, `Ni`, `d`/`Tr` and `illum` statements of the referenced
+//! material libraries. Statements the renderer has no use for (groups,
+//! smoothing groups, lines, texture maps, ...) are skipped.
+
+use ray_tracing_kernel as kernel;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use kernel::normal_map::*;
+use kernel::object::*;
+use kernel::texture::*;
+use kernel::vec3::*;
+
+use crate::error::*;
+use crate::geometry::*;
+
+/// Where to put a loaded model: positions are scaled about the origin of the
+/// file and then translated.
+#[derive(Clone, Copy)]
+pub struct Placement {
+    pub translation: Vec3,
+    pub scale: f32,
+}
+
+impl Default for Placement {
+    fn default() -> Placement {
+        Placement {
+            translation: Vec3::new(),
+            scale: 1.0,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MtlMaterial {
+    pub kd: Vec3,
+    pub ks: Vec3,
+    pub ke: Vec3,
+    pub ns: f32,
+    pub ni: f32,
+    pub d: f32,
+    pub illum: u32,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> MtlMaterial {
+        MtlMaterial {
+            kd: Vec3 {
+                x: 0.8,
+                y: 0.8,
+                z: 0.8,
+            },
+            ks: Vec3::new(),
+            ke: Vec3::new(),
+            ns: 0.0,
+            ni: 1.5,
+            d: 1.0,
+            illum: 2,
+        }
+    }
+}
+
+fn max_component(v: Vec3) -> f32 {
+    v.x.max(v.y).max(v.z)
+}
+
+impl MtlMaterial {
+    /// Picks the closest `ObjectMaterial`: emissive materials become lights,
+    /// transparent ones dielectrics, materials whose specular color dominates
+    /// become metals with a fuzz derived from the Phong exponent, everything
+    /// else is diffuse.
+    pub fn to_material(&self) -> ObjectMaterial {
+        if max_component(self.ke) > 0.0 {
+            return ObjectMaterial::DiffuseLight { emit: self.ke };
+        }
+        let transparent = match self.illum {
+            4 | 6 | 7 | 9 => true,
+            _ => self.d < 1.0,
+        };
+        if transparent {
+            return ObjectMaterial::Dielectric {
+                ref_idx: if self.ni > 0.0 { self.ni } else { 1.5 },
+            };
+        }
+        let reflective = self.illum == 3 || self.illum == 5 || self.illum == 8;
+        if reflective || max_component(self.ks) > max_component(self.kd) {
+            let fuzz = (2.0 / (self.ns.max(0.0) + 2.0)).sqrt();
+            return ObjectMaterial::Metal {
+                albedo: self.ks,
+                fuzz: fuzz.min(1.0),
+            };
+        }
+        ObjectMaterial::Lambertian {
+            albedo: Texture::solid(self.kd),
+            normal_map: NormalMap::None,
+        }
+    }
+}
+
+/// One mesh per material used by the file.
+pub struct ObjModel {
+    pub meshes: Vec<(Mesh, ObjectMaterial)>,
+}
+
+impl ObjModel {
+    pub fn add_to(&self, geometry: &mut Geometry) {
+        for (mesh, material) in &self.meshes {
+            geometry.add_mesh(mesh, *material);
+        }
+    }
+}
+
+struct Parser<'a> {
+    path: &'a Path,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<T, S: Into<String>>(&self, message: S) -> Result<T> {
+        Err(Error::Model {
+            path: self.path.display().to_string(),
+            line: self.line,
+            message: message.into(),
+        })
+    }
+
+    fn f32(&self, token: Option<&str>, what: &str) -> Result<f32> {
+        match token {
+            Some(token) => match token.parse::<f32>() {
+                Ok(x) if x.is_finite() => Ok(x),
+                _ => self.error(format!("invalid {}: {:?}", what, token)),
+            },
+            None => self.error(format!("missing {}", what)),
+        }
+    }
+
+    fn vec3<'b, I: Iterator<Item = &'b str>>(&self, tokens: &mut I, what: &str) -> Result<Vec3> {
+        Ok(Vec3 {
+            x: self.f32(tokens.next(), what)?,
+            y: self.f32(tokens.next(), what)?,
+            z: self.f32(tokens.next(), what)?,
+        })
+    }
+
+    /// Resolves a 1-based (or negative, relative to the end) OBJ index.
+    fn index(&self, token: &str, len: usize, what: &str) -> Result<usize> {
+        let i = match token.parse::<i64>() {
+            Ok(i) => i,
+            Err(_) => return self.error(format!("invalid {} index: {:?}", what, token)),
+        };
+        let resolved = if i > 0 { i - 1 } else { len as i64 + i };
+        if i == 0 || resolved < 0 || resolved >= len as i64 {
+            return self.error(format!(
+                "{} index {} out of range, {} defined so far",
+                what, i, len
+            ));
+        }
+        Ok(resolved as usize)
+    }
+}
+
+fn read(path: &Path) -> Result<String> {
+    fs::read_to_string(path).map_err(|e| Error::Model {
+        path: path.display().to_string(),
+        line: 0,
+        message: e.to_string(),
+    })
+}
+
+fn statement(line: &str) -> &str {
+    match line.find('#') {
+        Some(i) => &line[..i],
+        None => line,
+    }
+}
+
+pub fn load_mtl(path: &Path) -> Result<HashMap<String, MtlMaterial>> {
+    let s = read(path)?;
+    let mut parser = Parser { path, line: 0 };
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+    for (i, line) in s.lines().enumerate() {
+        parser.line = i + 1;
+        let mut tokens = statement(line).split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        if keyword == "newmtl" {
+            let name = match tokens.next() {
+                Some(name) => name.to_string(),
+                None => return parser.error("newmtl without a name"),
+            };
+            if let Some((name, material)) = current.take() {
+                materials.insert(name, material);
+            }
+            current = Some((name, MtlMaterial::default()));
+            continue;
+        }
+        let material = match current.as_mut() {
+            Some((_, material)) => material,
+            None => return parser.error(format!("{} before the first newmtl", keyword)),
+        };
+        match keyword {
+            "Kd" => material.kd = parser.vec3(&mut tokens, "Kd")?,
+            "Ks" => material.ks = parser.vec3(&mut tokens, "Ks")?,
+            "Ke" => material.ke = parser.vec3(&mut tokens, "Ke")?,
+            "Ns" => material.ns = parser.f32(tokens.next(), "Ns")?,
+            "Ni" => material.ni = parser.f32(tokens.next(), "Ni")?,
+            "d" => material.d = parser.f32(tokens.next(), "d")?,
+            "Tr" => material.d = 1.0 - parser.f32(tokens.next(), "Tr")?,
+            "illum" => {
+                material.illum = match tokens.next().map(|token| token.parse::<u32>()) {
+                    Some(Ok(illum)) if illum <= 10 => illum,
+                    _ => return parser.error("illum must be an integer between 0 and 10"),
+                }
+            }
+            _ => {}
+        }
+    }
+    if let Some((name, material)) = current {
+        materials.insert(name, material);
+    }
+    Ok(materials)
+}
+
+/// Vertices of the mesh being assembled for one material, deduplicated on
+/// their (position, texcoord, normal) index triple.
+#[derive(Default)]
+struct MeshBuilder {
+    mesh: Mesh,
+    has_normals: bool,
+    /// Set when some face vertex has no normal, in which case the mesh is
+    /// shaded flat since there is nothing to interpolate.
+    missing_normals: bool,
+    has_texcoords: bool,
+    vertex_ids: HashMap<(usize, Option<usize>, Option<usize>), u32>,
+    keys: Vec<(usize, Option<usize>, Option<usize>)>,
+}
+
+impl MeshBuilder {
+    fn vertex(&mut self, key: (usize, Option<usize>, Option<usize>)) -> u32 {
+        let keys = &mut self.keys;
+        *self.vertex_ids.entry(key).or_insert_with(|| {
+            keys.push(key);
+            keys.len() as u32 - 1
+        })
+    }
+
+    fn build(
+        mut self,
+        positions: &[Vec3],
+        texcoords: &[[f32; 2]],
+        normals: &[Vec3],
+        placement: Placement,
+    ) -> Mesh {
+        for &(v, vt, vn) in &self.keys {
+            self.mesh
+                .vertices
+                .push(placement.scale * positions[v] + placement.translation);
+            if self.has_texcoords {
+                self.mesh.texcoords.push(vt.map_or([0.0, 0.0], |vt| texcoords[vt]));
+            }
+            if self.has_normals && !self.missing_normals {
+                self.mesh
+                    .normals
+                    .push(vn.map_or(Vec3::new(), |vn| Vec3::unit_vector(normals[vn])));
+            }
+        }
+        self.mesh
+    }
+}
+
+pub fn load_obj<P: AsRef<Path>>(path: P, placement: Placement) -> Result<ObjModel> {
+    let path = path.as_ref();
+    parse_obj(&read(path)?, path, placement)
+}
+
+/// Parses the contents `s` of the OBJ file at `path`, which material
+/// libraries are looked up next to.
+fn parse_obj(s: &str, path: &Path, placement: Placement) -> Result<ObjModel> {
+    let mut parser = Parser { path, line: 0 };
+    if !(placement.scale > 0.0) {
+        return parser.error(format!("scale must be positive, got {}", placement.scale));
+    }
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_else(PathBuf::new);
+
+    let mut positions = vec![];
+    let mut texcoords = vec![];
+    let mut normals = vec![];
+    let mut materials: HashMap<String, MtlMaterial> = HashMap::new();
+    let mut builders: Vec<(ObjectMaterial, MeshBuilder)> = vec![];
+    let mut builder_ids: HashMap<Option<String>, usize> = HashMap::new();
+    let mut current_material: Option<String> = None;
+
+    for (i, line) in s.lines().enumerate() {
+        parser.line = i + 1;
+        let mut tokens = statement(line).split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        match keyword {
+            "v" => positions.push(parser.vec3(&mut tokens, "vertex position")?),
+            "vn" => {
+                let normal = parser.vec3(&mut tokens, "vertex normal")?;
+                if !(normal.squared_length() > 0.0) {
+                    return parser.error("vertex normal has zero length");
+                }
+                normals.push(normal);
+            }
+            "vt" => {
+                let u = parser.f32(tokens.next(), "texture coordinate")?;
+                let v = match tokens.next() {
+                    Some(v) => parser.f32(Some(v), "texture coordinate")?,
+                    None => 0.0,
+                };
+                texcoords.push([u, v]);
+            }
+            "mtllib" => {
+                for name in tokens {
+                    materials.extend(load_mtl(&dir.join(name))?);
+                }
+            }
+            "usemtl" => {
+                let name = match tokens.next() {
+                    Some(name) => name.to_string(),
+                    None => return parser.error("usemtl without a name"),
+                };
+                if !materials.contains_key(&name) {
+                    return parser.error(format!("unknown material {:?}", name));
+                }
+                current_material = Some(name);
+            }
+            "f" => {
+                let mut keys = vec![];
+                for token in tokens {
+                    let mut parts = token.split('/');
+                    let v = parser.index(parts.next().unwrap_or(""), positions.len(), "vertex")?;
+                    let vt = match parts.next() {
+                        Some("") | None => None,
+                        Some(vt) => Some(parser.index(vt, texcoords.len(), "texture coordinate")?),
+                    };
+                    let vn = match parts.next() {
+                        Some("") | None => None,
+                        Some(vn) => Some(parser.index(vn, normals.len(), "normal")?),
+                    };
+                    if parts.next().is_some() {
+                        return parser.error(format!("invalid face vertex: {:?}", token));
+                    }
+                    keys.push((v, vt, vn));
+                }
+                if keys.len() < 3 {
+                    return parser.error(format!(
+                        "face has {} vertices, at least 3 needed",
+                        keys.len()
+                    ));
+                }
+                let builder_id = match builder_ids.get(&current_material) {
+                    Some(&id) => id,
+                    None => {
+                        let material = match &current_material {
+                            Some(name) => materials[name].to_material(),
+                            None => MtlMaterial::default().to_material(),
+                        };
+                        builders.push((material, MeshBuilder::default()));
+                        builder_ids.insert(current_material.clone(), builders.len() - 1);
+                        builders.len() - 1
+                    }
+                };
+                let builder = &mut builders[builder_id].1;
+                if keys.iter().any(|&(_, _, vn)| vn.is_some()) {
+                    builder.has_normals = true;
+                }
+                if keys.iter().any(|&(_, _, vn)| vn.is_none()) {
+                    builder.missing_normals = true;
+                }
+                if keys.iter().any(|&(_, vt, _)| vt.is_some()) {
+                    builder.has_texcoords = true;
+                }
+                let ids: Vec<u32> = keys.into_iter().map(|key| builder.vertex(key)).collect();
+                for k in 1..ids.len() - 1 {
+                    builder.mesh.indices.push([ids[0], ids[k], ids[k + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let meshes = builders
+        .into_iter()
+        .map(|(material, builder)| {
+            (
+                builder.build(&positions, &texcoords, &normals, placement),
+                material,
+            )
+        })
+        .collect();
+    Ok(ObjModel { meshes })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<ObjModel> {
+        parse_obj(s, Path::new("test.obj"), Placement::default())
+    }
+
+    fn mesh(s: &str) -> Mesh {
+        let mut model = parse(s).unwrap_or_else(|e| panic!("{}", e));
+        assert_eq!(model.meshes.len(), 1);
+        model.meshes.pop().unwrap().0
+    }
+
+    fn error(s: &str) -> String {
+        parse(s)
+            .err()
+            .expect("the model should not parse")
+            .to_string()
+    }
+
+    const SQUARE: &str = "
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+";
+
+    #[test]
+    fn polygons_are_triangulated_as_fans() {
+        let quad = mesh(&format!("{}f 1 2 3 4\n", SQUARE));
+        assert_eq!(quad.vertices.len(), 4);
+        assert_eq!(quad.indices, vec![[0, 1, 2], [0, 2, 3]]);
+        let pentagon = mesh(&format!("{}v 0.5 2 0\nf 1 2 3 5 4\n", SQUARE));
+        assert_eq!(pentagon.indices, vec![[0, 1, 2], [0, 2, 3], [0, 3, 4]]);
+    }
+
+    #[test]
+    fn negative_indices_count_from_the_end() {
+        let relative = mesh(&format!("{}f -4 -3 -2 -1\n", SQUARE));
+        let absolute = mesh(&format!("{}f 1 2 3 4\n", SQUARE));
+        assert_eq!(relative.indices, absolute.indices);
+        let x: Vec<f32> = relative.vertices.iter().map(|v| v.x).collect();
+        assert_eq!(x, vec![0.0, 1.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn face_vertex_variants_are_read() {
+        let attributes = "vt 0 0\nvt 1 0\nvt 1 1\nvn 0 0 2\n";
+        let positions = mesh(&format!("{}f 1 2 3\n", SQUARE));
+        assert!(positions.texcoords.is_empty() && positions.normals.is_empty());
+
+        let textured = mesh(&format!("{}{}f 1/1 2/2 3/3\n", SQUARE, attributes));
+        assert_eq!(textured.texcoords, vec![[0.0, 0.0], [1.0, 0.0], [1.0, 1.0]]);
+        assert!(textured.normals.is_empty());
+
+        let normal = mesh(&format!("{}{}f 1//1 2//1 3//1\n", SQUARE, attributes));
+        assert!(normal.texcoords.is_empty());
+        assert_eq!(normal.normals.len(), 3);
+        // Normals are normalized.
+        assert!(normal.normals.iter().all(|n| n.z == 1.0));
+
+        let both = mesh(&format!("{}{}f 1/1/1 2/2/1 3/3/1\n", SQUARE, attributes));
+        assert_eq!(both.texcoords.len(), 3);
+        assert_eq!(both.normals.len(), 3);
+
+        // Vertices are shared only when all their indices are.
+        let split = mesh(&format!(
+            "{}{}f 1/1 2/2 3/3\nf 1/3 3/3 4/1\n",
+            SQUARE, attributes
+        ));
+        assert_eq!(split.vertices.len(), 5);
+    }
+
+    #[test]
+    fn out_of_range_indices_are_rejected() {
+        assert_eq!(
+            error(&format!("{}f 1 2 5\n", SQUARE)),
+            "test.obj:6: vertex index 5 out of range, 4 defined so far"
+        );
+        assert_eq!(
+            error(&format!("{}f 1 2 -5\n", SQUARE)),
+            "test.obj:6: vertex index -5 out of range, 4 defined so far"
+        );
+        assert_eq!(
+            error(&format!("{}f 1 2 0\n", SQUARE)),
+            "test.obj:6: vertex index 0 out of range, 4 defined so far"
+        );
+        assert_eq!(
+            error(&format!("{}vt 0 0\nf 1/1 2/2 3/1\n", SQUARE)),
+            "test.obj:7: texture coordinate index 2 out of range, 1 defined so far"
+        );
+    }
+
+    #[test]
+    fn errors_carry_the_line_number() {
+        assert_eq!(
+            error("v 0 0 0\n\n# comment\nv 1 x 0\n"),
+            "test.obj:4: invalid vertex position: \"x\""
+        );
+        assert_eq!(
+            error(&format!("{}f 1 2\n", SQUARE)),
+            "test.obj:6: face has 2 vertices, at least 3 needed"
+        );
+        assert_eq!(
+            error(&format!("{}usemtl missing\n", SQUARE)),
+            "test.obj:6: unknown material \"missing\""
+        );
+    }
+
+    #[test]
+    fn zero_length_normal_is_rejected() {
+        assert_eq!(
+            error(&format!("{}vn 0 0 0\nf 1//1 2//1 3//1\n", SQUARE)),
+            "test.obj:6: vertex normal has zero length"
+        );
+    }
+}
@@ -0,0 +1,174 @@
+//! Conversion of the linear radiance a render produces to displayable values.
+
+use ray_tracing_kernel as kernel;
+use std::str::FromStr;
+
+use kernel::vec3::*;
+
+use crate::error::*;
+
+/// Encoding applied to linear values before they are quantized.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transfer {
+    Linear,
+    /// `c^(1/gamma)`.
+    Gamma(f32),
+    /// The piecewise sRGB curve.
+    Srgb,
+}
+
+impl FromStr for Transfer {
+    type Err = String;
+
+    /// `linear`, `srgb` or a gamma value such as `2.2`.
+    fn from_str(s: &str) -> std::result::Result<Transfer, String> {
+        match s {
+            "linear" => Ok(Transfer::Linear),
+            "srgb" => Ok(Transfer::Srgb),
+            _ => s
+                .parse::<f32>()
+                .map(Transfer::Gamma)
+                .map_err(|_| format!("unknown transfer function: {}", s)),
+        }
+    }
+}
+
+impl Transfer {
+    fn encode(self, c: f32) -> f32 {
+        match self {
+            Transfer::Linear => c,
+            Transfer::Gamma(gamma) => c.powf(1.0 / gamma),
+            Transfer::Srgb => {
+                if c <= 0.003_130_8 {
+                    c * 12.92
+                } else {
+                    1.055 * c.powf(1.0 / 2.4) - 0.055
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct OutputTransform {
+    /// Exposure adjustment in stops.
+    pub exposure: f32,
+    pub transfer: Transfer,
+}
+
+impl Default for OutputTransform {
+    /// Gamma 2, as the examples were always written.
+    fn default() -> OutputTransform {
+        OutputTransform {
+            exposure: 0.0,
+            transfer: Transfer::Gamma(2.0),
+        }
+    }
+}
+
+impl OutputTransform {
+    pub fn validate(&self) -> Result<()> {
+        if !self.exposure.is_finite() {
+            return Err(Error::InvalidSettings(format!(
+                "exposure must be finite, got {}",
+                self.exposure
+            )));
+        }
+        match self.transfer {
+            Transfer::Gamma(gamma) if !(gamma > 0.0 && gamma.is_finite()) => Err(
+                Error::InvalidSettings(format!("gamma must be positive, got {}", gamma)),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Linear radiance scaled by the exposure, for HDR outputs.
+    pub fn expose(&self, v: Vec3) -> Vec3 {
+        v * 2f32.powf(self.exposure)
+    }
+
+    /// Exposed, clamped to [0, 1] and encoded by the transfer function.
+    pub fn encode(&self, v: Vec3) -> Vec3 {
+        let v = self.expose(v);
+        let encode = |c: f32| {
+            // NaN from a degenerate sample ends up black rather than white.
+            let c = if c > 0.0 { c.min(1.0) } else { 0.0 };
+            self.transfer.encode(c)
+        };
+        Vec3 {
+            x: encode(v.x),
+            y: encode(v.y),
+            z: encode(v.z),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(c: f32) -> Vec3 {
+        Vec3 { x: c, y: c, z: c }
+    }
+
+    fn encode(transfer: Transfer, exposure: f32, c: f32) -> f32 {
+        OutputTransform { exposure, transfer }.encode(gray(c)).x
+    }
+
+    fn assert_close(a: f32, e: f32) {
+        assert!((a - e).abs() < 1e-4, "{} != {}", a, e);
+    }
+
+    #[test]
+    fn srgb_encoding_of_known_values() {
+        assert_eq!(encode(Transfer::Srgb, 0.0, 0.0), 0.0);
+        assert_close(encode(Transfer::Srgb, 0.0, 1.0), 1.0);
+        // On the linear segment.
+        assert_close(encode(Transfer::Srgb, 0.0, 0.002), 0.025_84);
+        assert_close(encode(Transfer::Srgb, 0.0, 0.214_041), 0.5);
+        assert_close(encode(Transfer::Srgb, 0.0, 0.18), 0.461_356);
+    }
+
+    #[test]
+    fn gamma_encoding_of_known_values() {
+        assert_eq!(encode(Transfer::Gamma(2.0), 0.0, 0.25), 0.5);
+        assert_close(encode(Transfer::Gamma(2.2), 0.0, 0.5), 0.729_740);
+        assert_eq!(encode(Transfer::Linear, 0.0, 0.25), 0.25);
+        // One stop doubles the value before encoding.
+        assert_eq!(encode(Transfer::Gamma(2.0), 1.0, 0.125), 0.5);
+    }
+
+    #[test]
+    fn out_of_range_values_are_clamped() {
+        assert_close(encode(Transfer::Srgb, 0.0, 4.0), 1.0);
+        assert_eq!(encode(Transfer::Srgb, 0.0, -1.0), 0.0);
+        assert_eq!(encode(Transfer::Srgb, 0.0, std::f32::NAN), 0.0);
+    }
+
+    #[test]
+    fn validate_rejects_invalid_settings() {
+        assert!(OutputTransform::default().validate().is_ok());
+        for &exposure in &[std::f32::NAN, std::f32::INFINITY, std::f32::NEG_INFINITY] {
+            let output = OutputTransform {
+                exposure,
+                ..OutputTransform::default()
+            };
+            assert!(output.validate().is_err());
+        }
+        for &gamma in &[0.0, -2.2, std::f32::NAN] {
+            let output = OutputTransform {
+                transfer: Transfer::Gamma(gamma),
+                ..OutputTransform::default()
+            };
+            assert!(output.validate().is_err());
+        }
+    }
+
+    #[test]
+    fn transfer_is_parsed() {
+        assert_eq!("srgb".parse(), Ok(Transfer::Srgb));
+        assert_eq!("linear".parse(), Ok(Transfer::Linear));
+        assert_eq!("2.2".parse(), Ok(Transfer::Gamma(2.2)));
+        assert!("sRGB2".parse::<Transfer>().is_err());
+    }
+}
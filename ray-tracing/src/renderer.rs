@@ -0,0 +1,547 @@
+use core::cell::UnsafeCell;
+use ray_tracing_kernel as kernel;
+use device_runtime::{DeviceRuntime, HostRuntime, RuntimeError};
+use std::time::{Duration, Instant};
+
+use crate::background::*;
+use crate::error::*;
+use crate::geometry::*;
+
+use kernel::bvh::*;
+use kernel::camera::*;
+use kernel::light::*;
+use kernel::medium::*;
+use kernel::mesh::*;
+use kernel::object::*;
+use kernel::path::*;
+use kernel::ray_trace_args::*;
+use kernel::scene::*;
+use kernel::vec3::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Cpu,
+    Cuda,
+    Emulator,
+}
+
+impl std::str::FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Backend, String> {
+        match s {
+            "cpu" => Ok(Backend::Cpu),
+            "cuda" => Ok(Backend::Cuda),
+            "emulator" => Ok(Backend::Emulator),
+            _ => Err(format!("unknown backend: {}", s)),
+        }
+    }
+}
+
+/// How the device kernels split the work between threads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KernelMode {
+    /// One thread per sample, accumulated into the pixel with atomic adds.
+    Sample,
+    /// One thread per pixel, looping over its samples.
+    Pixel,
+}
+
+impl std::str::FromStr for KernelMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<KernelMode, String> {
+        match s {
+            "sample" => Ok(KernelMode::Sample),
+            "pixel" => Ok(KernelMode::Pixel),
+            _ => Err(format!("unknown kernel mode: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RenderSettings {
+    pub height: usize,
+    pub width: usize,
+    pub ray_per_pixel: usize,
+    pub backend: Backend,
+    /// Ignored by the CPU backend, which always loops per pixel.
+    pub kernel_mode: KernelMode,
+    /// Bounces after which a path is cut off.
+    pub max_depth: u32,
+    /// Bounces after which paths are terminated by Russian roulette.
+    pub rr_depth: u32,
+    /// Upper bound of the Russian roulette survival probability.
+    pub rr_probability: f32,
+    /// Trace wavelengths instead of RGB, for dispersion.
+    pub spectral: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        RenderSettings {
+            height: 400,
+            width: 600,
+            ray_per_pixel: 128,
+            backend: Backend::Cuda,
+            kernel_mode: KernelMode::Sample,
+            max_depth: 50,
+            rr_depth: 5,
+            rr_probability: 0.95,
+            spectral: false,
+        }
+    }
+}
+
+impl RenderSettings {
+    pub fn aspect(&self) -> f32 {
+        self.width as f32 / self.height as f32
+    }
+
+    /// Number of samples traced, one per kernel thread.
+    pub fn n_thread(&self) -> Option<usize> {
+        self.height
+            .checked_mul(self.width)
+            .and_then(|n| n.checked_mul(self.ray_per_pixel))
+    }
+
+    pub fn path(&self) -> PathSettings {
+        PathSettings {
+            max_depth: self.max_depth,
+            rr_depth: self.rr_depth,
+            rr_max_probability: self.rr_probability,
+            spectral: self.spectral,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.height == 0 || self.width == 0 {
+            return Err(Error::InvalidSettings(format!(
+                "image size must be non-zero, got {}x{}",
+                self.width, self.height
+            )));
+        }
+        if self.ray_per_pixel == 0 {
+            return Err(Error::InvalidSettings("ray_per_pixel must be non-zero".to_string()));
+        }
+        if self.max_depth == 0 {
+            return Err(Error::InvalidSettings("max_depth must be non-zero".to_string()));
+        }
+        if !(self.rr_probability > 0.0 && self.rr_probability <= 1.0) {
+            return Err(Error::InvalidSettings(format!(
+                "rr_probability must be in (0, 1], got {}",
+                self.rr_probability
+            )));
+        }
+        // The kernel computes its sample index from 32-bit thread indices.
+        match self.n_thread() {
+            Some(n_thread) if n_thread <= i32::max_value() as usize => Ok(()),
+            _ => Err(Error::InvalidSettings(format!(
+                "{}x{} pixels with {} rays per pixel exceed {} samples",
+                self.width,
+                self.height,
+                self.ray_per_pixel,
+                i32::max_value()
+            ))),
+        }
+    }
+}
+
+/// Linear float RGB pixels in row-major order, top row first.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+impl Image {
+    pub fn pixel(&self, x: usize, y: usize) -> Vec3 {
+        self.pixels[y * self.width + x]
+    }
+}
+
+/// Host copies of everything a render reads, in the layout the kernel expects.
+struct SceneData {
+    geometry: Geometry,
+    bvh: Vec<BvhNode>,
+    bounded_len: usize,
+    lights: Vec<Light>,
+    background: Background,
+    fog: Fog,
+    camera: Camera,
+    path: PathSettings,
+}
+
+impl SceneData {
+    fn new(
+        geometry: &Geometry,
+        background: &Background,
+        fog: Fog,
+        camera: Camera,
+        path: PathSettings,
+    ) -> SceneData {
+        let mut geometry = geometry.clone();
+        let mesh = MeshBuffers {
+            vertices: &geometry.vertices,
+            normals: &geometry.normals,
+            texcoords: &geometry.texcoords,
+            indices: &geometry.indices,
+        };
+        let (bvh, bounded_len) = crate::bvh::build(&mut geometry.objects, &mesh);
+        // Indices into the objects as reordered by the BVH build.
+        let lights = geometry
+            .objects
+            .iter()
+            .enumerate()
+            .filter(|(_, object)| match object.material {
+                ObjectMaterial::DiffuseLight { .. } => is_sampleable(&object.shape),
+                _ => false,
+            })
+            .map(|(i, _)| Light { object: i as u32 })
+            .collect();
+        SceneData {
+            geometry,
+            bvh,
+            bounded_len,
+            lights,
+            background: background.clone(),
+            fog,
+            camera,
+            path,
+        }
+    }
+
+    fn scene(&self) -> Scene {
+        let (background, environment) = self.background.kernel();
+        Scene {
+            objects: &self.geometry.objects,
+            bvh: &self.bvh,
+            bounded_len: self.bounded_len,
+            mesh: self.geometry.mesh_buffers(),
+            prototypes: &self.geometry.prototypes,
+            prototype_bvh: &self.geometry.prototype_bvh,
+            transforms: &self.geometry.transforms,
+            media: &self.geometry.media,
+            fog: self.fog,
+            lights: &self.lights,
+            texels: &self.geometry.texels,
+            background,
+            environment,
+            camera: self.camera,
+            path: self.path,
+        }
+    }
+}
+
+pub struct Renderer {
+    settings: RenderSettings,
+}
+
+impl Renderer {
+    pub fn new(settings: RenderSettings) -> Result<Renderer> {
+        settings.validate()?;
+        Ok(Renderer { settings })
+    }
+
+    pub fn settings(&self) -> &RenderSettings {
+        &self.settings
+    }
+
+    pub fn render(
+        &self,
+        geometry: &Geometry,
+        background: &Background,
+        fog: Fog,
+        camera: Camera,
+    ) -> Result<Image> {
+        let h = self.settings.height;
+        let w = self.settings.width;
+        let ray_per_pixel = self.settings.ray_per_pixel;
+        let mode = self.settings.kernel_mode;
+        let data = SceneData::new(geometry, background, fog, camera, self.settings.path());
+        let pixels = match self.settings.backend {
+            Backend::Cuda => render_device(&mut cuda_runtime()?, &data, h, w, ray_per_pixel, mode)?,
+            Backend::Emulator => {
+                let mut runtime = HostRuntime::new();
+                render_device(&mut runtime, &data, h, w, ray_per_pixel, mode)?
+            }
+            Backend::Cpu => crate::cpu::render(&data.scene(), h, w, ray_per_pixel),
+        };
+        Ok(Image {
+            width: w,
+            height: h,
+            pixels,
+        })
+    }
+
+    /// Renders with both kernel modes and returns the image with the time of
+    /// each launch, excluding the device setup and uploads they share. The
+    /// kernel mode of the settings is ignored; the CPU backend has none.
+    pub fn benchmark(
+        &self,
+        geometry: &Geometry,
+        background: &Background,
+        fog: Fog,
+        camera: Camera,
+    ) -> Result<(Image, Vec<(KernelMode, Duration)>)> {
+        let h = self.settings.height;
+        let w = self.settings.width;
+        let ray_per_pixel = self.settings.ray_per_pixel;
+        let data = SceneData::new(geometry, background, fog, camera, self.settings.path());
+        let (pixels, times) = match self.settings.backend {
+            Backend::Cuda => benchmark_device(&mut cuda_runtime()?, &data, h, w, ray_per_pixel)?,
+            Backend::Emulator => {
+                benchmark_device(&mut HostRuntime::new(), &data, h, w, ray_per_pixel)?
+            }
+            Backend::Cpu => {
+                return Err(Error::InvalidSettings(
+                    "only the device backends can be benchmarked".to_string(),
+                ))
+            }
+        };
+        let image = Image {
+            width: w,
+            height: h,
+            pixels,
+        };
+        Ok((image, times))
+    }
+}
+
+fn cuda_runtime() -> Result<cuda_tools::runtime::Runtime> {
+    cuda_tools::runtime::Runtime::new(0, crate::KERNEL)
+        .map_err(|e| Error::DeviceInit(RuntimeError(format!("{:?}", e))))
+}
+
+fn render_device<R: DeviceRuntime>(
+    runtime: &mut R,
+    data: &SceneData,
+    h: usize,
+    w: usize,
+    ray_per_pixel: usize,
+    mode: KernelMode,
+) -> Result<Vec<Vec3>> {
+    record_kernels(runtime);
+    let args = upload(runtime, data, h, w, ray_per_pixel)?;
+    launch(runtime, &args, mode)?;
+    read_image(runtime, &args)
+}
+
+/// Times one launch of each kernel mode on the same uploaded scene, after an
+/// untimed warm-up launch of each, and returns the image of the pixel mode.
+fn benchmark_device<R: DeviceRuntime>(
+    runtime: &mut R,
+    data: &SceneData,
+    h: usize,
+    w: usize,
+    ray_per_pixel: usize,
+) -> Result<(Vec<Vec3>, Vec<(KernelMode, Duration)>)> {
+    record_kernels(runtime);
+    let runtime: &R = runtime;
+    let args = upload(runtime, data, h, w, ray_per_pixel)?;
+    // Launches are asynchronous on a device; reading back a byte waits for them.
+    let fence = runtime.alloc_slice(&[0u8]).map_err(Error::Alloc)?;
+    let synchronize = || runtime.to_host(&fence).map_err(Error::Readback);
+    // Sample mode adds onto the image and pixel mode overwrites it, so pixel
+    // mode runs last to leave a single render behind.
+    let modes = [KernelMode::Sample, KernelMode::Pixel];
+    for &mode in &modes {
+        launch(runtime, &args, mode)?;
+    }
+    synchronize()?;
+    let mut times = vec![];
+    for &mode in &modes {
+        let start = Instant::now();
+        launch(runtime, &args, mode)?;
+        synchronize()?;
+        times.push((mode, start.elapsed()));
+    }
+    Ok((read_image(runtime, &args)?, times))
+}
+
+fn record_kernels<R: DeviceRuntime>(runtime: &mut R) {
+    runtime.record_function_name(kernel::kernel::ray_trace, "ray_trace");
+    runtime.record_function_name(kernel::kernel::ray_trace_pixel, "ray_trace_pixel");
+}
+
+/// Uploads the scene and a zeroed `h * w` image and returns the kernel
+/// arguments referring to them.
+fn upload<'a, R: DeviceRuntime>(
+    runtime: &'a R,
+    data: &SceneData,
+    h: usize,
+    w: usize,
+    ray_per_pixel: usize,
+) -> Result<RayTraceArgs<'a>> {
+    let n = h * w;
+
+    let mut image_h = vec![];
+    for _ in 0..n {
+        image_h.push(UnsafeCell::new(Vec3::new()));
+    }
+    let image_d = runtime.alloc_slice(&image_h).map_err(Error::Alloc)?;
+
+    let geometry = &data.geometry;
+    let objects_d = runtime.alloc_slice(&geometry.objects).map_err(Error::Alloc)?;
+    let bvh_d = runtime.alloc_slice(&data.bvh).map_err(Error::Alloc)?;
+    let vertices_d = runtime.alloc_slice(&geometry.vertices).map_err(Error::Alloc)?;
+    let normals_d = runtime.alloc_slice(&geometry.normals).map_err(Error::Alloc)?;
+    let texcoords_d = runtime.alloc_slice(&geometry.texcoords).map_err(Error::Alloc)?;
+    let indices_d = runtime.alloc_slice(&geometry.indices).map_err(Error::Alloc)?;
+    let prototypes_d = runtime.alloc_slice(&geometry.prototypes).map_err(Error::Alloc)?;
+    let prototype_bvh_d = runtime
+        .alloc_slice(&geometry.prototype_bvh)
+        .map_err(Error::Alloc)?;
+    let transforms_d = runtime.alloc_slice(&geometry.transforms).map_err(Error::Alloc)?;
+    let media_d = runtime.alloc_slice(&geometry.media).map_err(Error::Alloc)?;
+    let texels_d = runtime.alloc_slice(&geometry.texels).map_err(Error::Alloc)?;
+    let lights_d = runtime.alloc_slice(&data.lights).map_err(Error::Alloc)?;
+    let (background, environment) = data.background.kernel();
+    let environment_d = runtime.alloc_slice(environment).map_err(Error::Alloc)?;
+
+    Ok(RayTraceArgs {
+        image_len: n,
+        image: image_d,
+        h,
+        w,
+        objects_len: geometry.objects.len(),
+        objects: objects_d,
+        bvh_len: data.bvh.len(),
+        bvh: bvh_d,
+        bounded_len: data.bounded_len,
+        vertices_len: geometry.vertices.len(),
+        vertices: vertices_d,
+        normals_len: geometry.normals.len(),
+        normals: normals_d,
+        texcoords_len: geometry.texcoords.len(),
+        texcoords: texcoords_d,
+        indices_len: geometry.indices.len(),
+        indices: indices_d,
+        prototypes_len: geometry.prototypes.len(),
+        prototypes: prototypes_d,
+        prototype_bvh_len: geometry.prototype_bvh.len(),
+        prototype_bvh: prototype_bvh_d,
+        transforms_len: geometry.transforms.len(),
+        transforms: transforms_d,
+        media_len: geometry.media.len(),
+        media: media_d,
+        fog: data.fog,
+        lights_len: data.lights.len(),
+        lights: lights_d,
+        texels_len: geometry.texels.len(),
+        texels: texels_d,
+        background,
+        environment_len: environment.len(),
+        environment: environment_d,
+        ray_per_pixel,
+        camera: data.camera,
+        path: data.path,
+    })
+}
+
+fn launch<R: DeviceRuntime>(runtime: &R, args: &RayTraceArgs, mode: KernelMode) -> Result<()> {
+    let n = args.h * args.w;
+    let (ray_trace, n_thread): (extern "ptx-kernel" fn(&RayTraceArgs), usize) = match mode {
+        KernelMode::Sample => (kernel::kernel::ray_trace, n * args.ray_per_pixel),
+        KernelMode::Pixel => (kernel::kernel::ray_trace_pixel, n),
+    };
+    let m = 64;
+    runtime
+        .launch(ray_trace, args, (n_thread + m - 1) / m, 1, 1, m, 1, 1)
+        .map_err(Error::Launch)
+}
+
+fn read_image<R: DeviceRuntime>(runtime: &R, args: &RayTraceArgs) -> Result<Vec<Vec3>> {
+    let image = runtime.to_host(&args.image).map_err(Error::Readback)?;
+    Ok(image.into_iter().map(|x| x.into_inner()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 105 samples and 35 pixels: neither fills the 64-thread blocks.
+    const H: usize = 5;
+    const W: usize = 7;
+    const RAY_PER_PIXEL: usize = 3;
+
+    fn scene_data() -> SceneData {
+        let settings = RenderSettings {
+            height: H,
+            width: W,
+            ray_per_pixel: RAY_PER_PIXEL,
+            max_depth: 8,
+            ..RenderSettings::default()
+        };
+        SceneData::new(
+            &Geometry::from(crate::small_scene(0)),
+            &Background::default(),
+            Fog::none(),
+            crate::default_camera(settings.aspect()),
+            settings.path(),
+        )
+    }
+
+    fn assert_close(actual: &[Vec3], expected: &[Vec3], tolerance: f32) {
+        assert_eq!(actual.len(), expected.len());
+        for (i, (a, e)) in actual.iter().zip(expected).enumerate() {
+            for &(a, e) in &[(a.x, e.x), (a.y, e.y), (a.z, e.z)] {
+                assert!(
+                    (a - e).abs() <= tolerance * e.abs().max(1.0),
+                    "pixel {}: {} != {}",
+                    i,
+                    a,
+                    e
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn host_runtime_pixel_mode_matches_cpu() {
+        let data = scene_data();
+        let mut runtime = HostRuntime::new();
+        let image =
+            render_device(&mut runtime, &data, H, W, RAY_PER_PIXEL, KernelMode::Pixel).unwrap();
+        // Both sum the samples of a pixel in the same order.
+        let expected = crate::cpu::render(&data.scene(), H, W, RAY_PER_PIXEL);
+        assert_close(&image, &expected, 0.0);
+    }
+
+    #[test]
+    fn host_runtime_sample_mode_matches_cpu() {
+        let data = scene_data();
+        let mut runtime = HostRuntime::new();
+        let image =
+            render_device(&mut runtime, &data, H, W, RAY_PER_PIXEL, KernelMode::Sample).unwrap();
+        // Atomic adds sum the samples in any order.
+        let expected = crate::cpu::render(&data.scene(), H, W, RAY_PER_PIXEL);
+        assert_close(&image, &expected, 1e-5);
+    }
+
+    #[test]
+    fn benchmark_leaves_the_pixel_mode_image() {
+        let data = scene_data();
+        let mut runtime = HostRuntime::new();
+        let (image, times) = benchmark_device(&mut runtime, &data, H, W, RAY_PER_PIXEL).unwrap();
+        let modes: Vec<_> = times.iter().map(|&(mode, _)| mode).collect();
+        assert_eq!(modes, [KernelMode::Sample, KernelMode::Pixel]);
+        let expected = crate::cpu::render(&data.scene(), H, W, RAY_PER_PIXEL);
+        assert_close(&image, &expected, 0.0);
+    }
+
+    #[test]
+    fn benchmark_rejects_cpu_backend() {
+        let settings = RenderSettings {
+            backend: Backend::Cpu,
+            ..RenderSettings::default()
+        };
+        let renderer = Renderer::new(settings).unwrap();
+        let geometry = Geometry::from(crate::small_scene(0));
+        let camera = crate::default_camera(settings.aspect());
+        match renderer.benchmark(&geometry, &Background::default(), Fog::none(), camera) {
+            Err(Error::InvalidSettings(_)) => {}
+            _ => panic!("benchmarked the CPU backend"),
+        }
+    }
+}
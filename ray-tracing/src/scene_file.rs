@@ -0,0 +1,1387 @@
+//! Declarative scene description, read from TOML:
+//!
+//! ```toml
+//! [camera]
+//! lookfrom = [10.0, 2.0, 2.5]
+//! lookat = [0.0, 0.0, -1.0]
+//! vfov = 30.0
+//!
+//! [render]
+//! width = 600
+//! height = 400
+//! ray_per_pixel = 128
+//! max_depth = 50
+//!
+//! [[objects]]
+//! shape = { type = "sphere", center = [0.0, 0.0, -1.0], radius = 0.5 }
+//! material = { type = "lambertian", albedo = [0.1, 0.2, 0.5] }
+//!
+//! [[objects]]
+//! shape = { type = "triangle", vertices = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]] }
+//! material = { type = "metal", albedo = [0.8, 0.8, 0.8], fuzz = 0.1 }
+//!
+//! [[objects]]
+//! shape = { type = "plane", point = [0.0, -0.5, 0.0], normal = [0.0, 1.0, 0.0] }
+//! material = { type = "lambertian", albedo = { type = "checker", odd = [0.2, 0.3, 0.1], even = [0.9, 0.9, 0.9] } }
+//!
+//! [[objects]]
+//! shape = { type = "sphere", center = [-2.0, 0.0, -1.0], radius = 0.5 }
+//! material = { type = "lambertian", albedo = { type = "image", path = "earth.png" } }
+//!
+//! [[objects]]
+//! shape = { type = "box", min = [1.0, -0.5, -2.0], max = [1.5, 0.5, -1.5] }
+//! material = { type = "lambertian", albedo = [0.7, 0.7, 0.7] }
+//!
+//! [[objects]]
+//! shape = { type = "sphere", center = [2.0, 0.0, -1.0], radius = 0.5 }
+//! material = { type = "conductor", ior = "gold", roughness = 0.3 }
+//!
+//! [[meshes]]
+//! path = "bunny.obj"
+//! translate = [0.0, 0.5, 0.0]
+//! scale = 2.0
+//!
+//! [[prototypes]]
+//! name = "pillar"
+//! shape = { type = "box", min = [-0.1, 0.0, -0.1], max = [0.1, 1.0, 0.1] }
+//!
+//! [[instances]]
+//! prototype = "pillar"
+//! translate = [2.0, -0.5, -1.0]
+//! rotate = [0.0, 45.0, 0.0]
+//! scale = [1.0, 2.0, 1.0]
+//! material = { type = "metal", albedo = [0.9, 0.9, 0.9], fuzz = 0.0 }
+//!
+//! [[media]]
+//! boundary = { type = "sphere", center = [0.0, 0.0, -1.0], radius = 0.5 }
+//! density = 2.0
+//! albedo = [0.9, 0.9, 0.9]
+//!
+//! [fog]
+//! density = 0.01
+//! albedo = [1.0, 1.0, 1.0]
+//!
+//! [background]
+//! type = "environment"
+//! path = "sky.hdr"
+//! rotation = 90.0
+//! intensity = 1.5
+//! ```
+
+use ray_tracing_kernel as kernel;
+use serde::{de, Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use kernel::camera::*;
+use kernel::matrix::*;
+use kernel::medium::*;
+use kernel::microfacet::*;
+use kernel::normal_map::*;
+use kernel::object::*;
+use kernel::principled::*;
+use kernel::spectrum::*;
+use kernel::texture::*;
+use kernel::vec3::*;
+
+use crate::background::*;
+use crate::error::*;
+use crate::geometry::*;
+use crate::hdr_image::*;
+use crate::obj::*;
+
+fn vec3(v: [f32; 3]) -> Vec3 {
+    Vec3 {
+        x: v[0],
+        y: v[1],
+        z: v[2],
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraDesc {
+    pub lookfrom: [f32; 3],
+    pub lookat: [f32; 3],
+    #[serde(default = "CameraDesc::default_vup")]
+    pub vup: [f32; 3],
+    /// Vertical field of view in degrees.
+    #[serde(deserialize_with = "deserialize_vfov")]
+    pub vfov: f32,
+    #[serde(default)]
+    pub aperture: f32,
+    /// Distance to the plane in focus, `|lookfrom - lookat|` when omitted.
+    pub focus_dist: Option<f32>,
+    /// Shutter interval, empty by default so that nothing is motion blurred.
+    #[serde(default)]
+    pub time0: f32,
+    #[serde(default)]
+    pub time1: f32,
+}
+
+fn deserialize_vfov<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<f32, D::Error> {
+    let vfov = f32::deserialize(deserializer)?;
+    if !(0.0 < vfov && vfov < 180.0) {
+        return Err(de::Error::custom(format!(
+            "vfov must be between 0 and 180 degrees, got {}",
+            vfov
+        )));
+    }
+    Ok(vfov)
+}
+
+impl CameraDesc {
+    fn default_vup() -> [f32; 3] {
+        [0.0, 1.0, 0.0]
+    }
+
+    pub fn camera(&self, aspect: f32) -> Camera {
+        let lookfrom = vec3(self.lookfrom);
+        let lookat = vec3(self.lookat);
+        let focus_dist = self
+            .focus_dist
+            .unwrap_or_else(|| (lookfrom - lookat).length());
+        crate::new_camera(
+            lookfrom,
+            lookat,
+            vec3(self.vup),
+            self.vfov,
+            aspect,
+            self.aperture,
+            focus_dist,
+            self.time0,
+            self.time1,
+        )
+    }
+}
+
+/// Render settings stored in the scene. Options given on the command line take
+/// precedence over these.
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RenderDesc {
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub ray_per_pixel: Option<usize>,
+    pub max_depth: Option<u32>,
+    pub rr_depth: Option<u32>,
+    pub rr_probability: Option<f32>,
+    pub spectral: Option<bool>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum ShapeDesc {
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+    },
+    MovingSphere {
+        center0: [f32; 3],
+        center1: [f32; 3],
+        time0: f32,
+        time1: f32,
+        radius: f32,
+    },
+    Triangle {
+        vertices: [[f32; 3]; 3],
+        normals: Option<[[f32; 3]; 3]>,
+    },
+    Plane {
+        point: [f32; 3],
+        normal: [f32; 3],
+    },
+    XyRect {
+        x0: f32,
+        x1: f32,
+        y0: f32,
+        y1: f32,
+        k: f32,
+        #[serde(default)]
+        flip: bool,
+    },
+    XzRect {
+        x0: f32,
+        x1: f32,
+        z0: f32,
+        z1: f32,
+        k: f32,
+        #[serde(default)]
+        flip: bool,
+    },
+    YzRect {
+        y0: f32,
+        y1: f32,
+        z0: f32,
+        z1: f32,
+        k: f32,
+        #[serde(default)]
+        flip: bool,
+    },
+    Box {
+        min: [f32; 3],
+        max: [f32; 3],
+    },
+}
+
+/// The shapes one `[[objects]]` entry expands to: a box becomes its six faces.
+#[derive(Deserialize)]
+#[serde(try_from = "ShapeDesc")]
+struct Shape(Vec<ObjectShape>);
+
+fn check_radius(radius: f32) -> std::result::Result<(), String> {
+    // A negative radius is allowed: it flips the normals, which is how hollow
+    // glass spheres are built.
+    if radius == 0.0 || !radius.is_finite() {
+        return Err(format!("sphere radius must be non-zero, got {}", radius));
+    }
+    Ok(())
+}
+
+fn check_range(name: &str, r0: f32, r1: f32) -> std::result::Result<(), String> {
+    if !(r0 < r1 && r0.is_finite() && r1.is_finite()) {
+        return Err(format!(
+            "{0}0 must be less than {0}1, got {1} and {2}",
+            name, r0, r1
+        ));
+    }
+    Ok(())
+}
+
+impl TryFrom<ShapeDesc> for Shape {
+    type Error = String;
+
+    fn try_from(desc: ShapeDesc) -> std::result::Result<Shape, String> {
+        match desc {
+            ShapeDesc::Sphere { center, radius } => {
+                check_radius(radius)?;
+                Ok(Shape(vec![ObjectShape::Sphere {
+                    center: vec3(center),
+                    radius,
+                }]))
+            }
+            ShapeDesc::MovingSphere {
+                center0,
+                center1,
+                time0,
+                time1,
+                radius,
+            } => {
+                check_radius(radius)?;
+                if !(time0 <= time1) {
+                    return Err(format!(
+                        "time0 must not be after time1, got {} and {}",
+                        time0, time1
+                    ));
+                }
+                Ok(Shape(vec![ObjectShape::MovingSphere {
+                    center0: vec3(center0),
+                    center1: vec3(center1),
+                    time0,
+                    time1,
+                    radius,
+                }]))
+            }
+            ShapeDesc::Triangle { vertices, normals } => {
+                let vertices = [vec3(vertices[0]), vec3(vertices[1]), vec3(vertices[2])];
+                let n = Vec3::cross(vertices[1] - vertices[0], vertices[2] - vertices[0]);
+                if !(n.length() > 0.0) {
+                    return Err("triangle is degenerate".to_string());
+                }
+                Ok(Shape(vec![ObjectShape::Triangle {
+                    vertices,
+                    normals: normals.map(|n| [vec3(n[0]), vec3(n[1]), vec3(n[2])]),
+                }]))
+            }
+            ShapeDesc::Plane { point, normal } => {
+                if !(vec3(normal).length() > 0.0) {
+                    return Err("plane normal must be non-zero".to_string());
+                }
+                Ok(Shape(vec![ObjectShape::Plane {
+                    point: vec3(point),
+                    normal: vec3(normal),
+                }]))
+            }
+            ShapeDesc::XyRect {
+                x0,
+                x1,
+                y0,
+                y1,
+                k,
+                flip,
+            } => {
+                check_range("x", x0, x1)?;
+                check_range("y", y0, y1)?;
+                Ok(Shape(vec![ObjectShape::XyRect {
+                    x0,
+                    x1,
+                    y0,
+                    y1,
+                    k,
+                    flip,
+                }]))
+            }
+            ShapeDesc::XzRect {
+                x0,
+                x1,
+                z0,
+                z1,
+                k,
+                flip,
+            } => {
+                check_range("x", x0, x1)?;
+                check_range("z", z0, z1)?;
+                Ok(Shape(vec![ObjectShape::XzRect {
+                    x0,
+                    x1,
+                    z0,
+                    z1,
+                    k,
+                    flip,
+                }]))
+            }
+            ShapeDesc::YzRect {
+                y0,
+                y1,
+                z0,
+                z1,
+                k,
+                flip,
+            } => {
+                check_range("y", y0, y1)?;
+                check_range("z", z0, z1)?;
+                Ok(Shape(vec![ObjectShape::YzRect {
+                    y0,
+                    y1,
+                    z0,
+                    z1,
+                    k,
+                    flip,
+                }]))
+            }
+            ShapeDesc::Box { min, max } => {
+                if (0..3).any(|i| !(min[i] < max[i])) {
+                    return Err(format!(
+                        "box min must be less than max on every axis, got {:?} and {:?}",
+                        min, max
+                    ));
+                }
+                Ok(Shape(box_faces(vec3(min), vec3(max)).to_vec()))
+            }
+        }
+    }
+}
+
+fn default_checker_scale() -> f32 {
+    10.0
+}
+
+fn default_noise_color() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+fn default_turbulence_depth() -> u32 {
+    7
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    Solid {
+        color: [f32; 3],
+    },
+    Checker {
+        odd: [f32; 3],
+        even: [f32; 3],
+        #[serde(default = "default_checker_scale")]
+        scale: f32,
+    },
+    Noise {
+        #[serde(default = "default_noise_color")]
+        color: [f32; 3],
+        scale: f32,
+    },
+    Turbulence {
+        #[serde(default = "default_noise_color")]
+        color: [f32; 3],
+        scale: f32,
+        #[serde(default = "default_turbulence_depth")]
+        depth: u32,
+    },
+    /// An image file, its path relative to the scene file.
+    Image {
+        path: PathBuf,
+    },
+}
+
+/// A plain color or a texture.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AlbedoDesc {
+    Color([f32; 3]),
+    Texture(TextureDesc),
+}
+
+/// The name of a measured metal or an explicit complex index of refraction.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum IorDesc {
+    Preset(String),
+    Complex { eta: [f32; 3], k: [f32; 3] },
+}
+
+/// The name of a glass or the coefficients of a dispersion formula.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DispersionDesc {
+    Preset(String),
+    Cauchy { a: f32, b: f32 },
+    Sellmeier { b: [f32; 3], c: [f32; 3] },
+}
+
+fn default_strength() -> f32 {
+    1.0
+}
+
+/// An image perturbing the shading normals of a material, its path relative
+/// to the scene file.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum NormalMapDesc {
+    Normal {
+        path: PathBuf,
+        #[serde(default = "default_strength")]
+        strength: f32,
+    },
+    Bump {
+        path: PathBuf,
+        scale: f32,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian(LambertianDesc),
+    Metal { albedo: [f32; 3], fuzz: f32 },
+    Dielectric { ref_idx: f32 },
+    DispersiveDielectric { ior: DispersionDesc },
+    Conductor { ior: IorDesc, roughness: f32 },
+    RoughDielectric { ref_idx: f32, roughness: f32 },
+    Principled(PrincipledDesc),
+    DiffuseLight { emit: [f32; 3] },
+    Isotropic { albedo: [f32; 3] },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LambertianDesc {
+    albedo: AlbedoDesc,
+    #[serde(default)]
+    normal_map: Option<NormalMapDesc>,
+}
+
+fn default_roughness() -> f32 {
+    0.5
+}
+
+fn default_specular() -> f32 {
+    0.5
+}
+
+fn default_clearcoat_roughness() -> f32 {
+    0.1
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PrincipledDesc {
+    base_color: AlbedoDesc,
+    #[serde(default)]
+    metallic: f32,
+    #[serde(default = "default_roughness")]
+    roughness: f32,
+    #[serde(default = "default_specular")]
+    specular: f32,
+    #[serde(default)]
+    clearcoat: f32,
+    #[serde(default = "default_clearcoat_roughness")]
+    clearcoat_roughness: f32,
+    #[serde(default)]
+    transmission: f32,
+    #[serde(default)]
+    sheen: f32,
+    #[serde(default)]
+    normal_map: Option<NormalMapDesc>,
+}
+
+/// A material and the images its albedo and normal map sample, which are
+/// only loaded once the scene's directory and geometry are known.
+#[derive(Deserialize)]
+#[serde(try_from = "MaterialDesc")]
+struct Material {
+    material: ObjectMaterial,
+    image: Option<PathBuf>,
+    normal_image: Option<PathBuf>,
+}
+
+impl From<ObjectMaterial> for Material {
+    fn from(material: ObjectMaterial) -> Material {
+        Material {
+            material,
+            image: None,
+            normal_image: None,
+        }
+    }
+}
+
+fn check_albedo(albedo: [f32; 3]) -> std::result::Result<Vec3, String> {
+    if albedo.iter().any(|&c| !(0.0 <= c && c <= 1.0)) {
+        return Err(format!(
+            "albedo components must be between 0 and 1, got {:?}",
+            albedo
+        ));
+    }
+    Ok(vec3(albedo))
+}
+
+fn check_scale(scale: f32) -> std::result::Result<f32, String> {
+    if !(scale > 0.0 && scale.is_finite()) {
+        return Err(format!("texture scale must be positive, got {}", scale));
+    }
+    Ok(scale)
+}
+
+fn check_roughness(roughness: f32) -> std::result::Result<f32, String> {
+    if !(0.0 <= roughness && roughness <= 1.0) {
+        return Err(format!(
+            "roughness must be between 0 and 1, got {}",
+            roughness
+        ));
+    }
+    Ok(roughness)
+}
+
+fn check_unit(name: &str, value: f32) -> std::result::Result<f32, String> {
+    if !(0.0 <= value && value <= 1.0) {
+        return Err(format!("{} must be between 0 and 1, got {}", name, value));
+    }
+    Ok(value)
+}
+
+fn check_ref_idx(ref_idx: f32) -> std::result::Result<f32, String> {
+    if !(ref_idx > 0.0 && ref_idx.is_finite()) {
+        return Err(format!("ref_idx must be positive, got {}", ref_idx));
+    }
+    Ok(ref_idx)
+}
+
+fn dispersion(desc: DispersionDesc) -> std::result::Result<Ior, String> {
+    let ior = match desc {
+        DispersionDesc::Preset(name) => match name.as_str() {
+            "bk7" => Ior::Sellmeier {
+                b: [1.039_612_1, 0.231_792_34, 1.010_469_5],
+                c: [0.006_000_699, 0.020_017_914, 103.560_65],
+            },
+            "sf11" => Ior::Sellmeier {
+                b: [1.737_597, 0.313_747_35, 1.898_781],
+                c: [0.013_188_707, 0.062_306_814, 155.236_29],
+            },
+            _ => return Err(format!("unknown glass {:?}, expected bk7 or sf11", name)),
+        },
+        DispersionDesc::Cauchy { a, b } => Ior::Cauchy { a, b },
+        DispersionDesc::Sellmeier { b, c } => Ior::Sellmeier { b, c },
+    };
+    // Every wavelength spectral rendering can trace must see a usable index.
+    let steps = 34;
+    for i in 0..=steps {
+        let lambda = LAMBDA_MIN + (LAMBDA_MAX - LAMBDA_MIN) * i as f32 / steps as f32;
+        let n = ior.at(lambda);
+        if !(n > 0.0 && n.is_finite()) {
+            return Err(format!(
+                "index of refraction must be positive, got {} at {} nm",
+                n, lambda
+            ));
+        }
+    }
+    Ok(ior)
+}
+
+/// The normal map `desc` describes, sampling a placeholder until its image
+/// is loaded, and the path of that image.
+fn normal_map(
+    desc: Option<NormalMapDesc>,
+) -> std::result::Result<(NormalMap, Option<PathBuf>), String> {
+    let placeholder = Texture::solid(Vec3::new());
+    match desc {
+        None => Ok((NormalMap::None, None)),
+        Some(NormalMapDesc::Normal { path, strength }) => {
+            if !(strength >= 0.0 && strength.is_finite()) {
+                return Err(format!(
+                    "normal map strength must be non-negative, got {}",
+                    strength
+                ));
+            }
+            let normal_map = NormalMap::Normal {
+                map: placeholder,
+                strength,
+            };
+            Ok((normal_map, Some(path)))
+        }
+        Some(NormalMapDesc::Bump { path, scale }) => {
+            if !scale.is_finite() {
+                return Err(format!("bump map scale must be finite, got {}", scale));
+            }
+            let normal_map = NormalMap::Bump {
+                map: placeholder,
+                scale,
+            };
+            Ok((normal_map, Some(path)))
+        }
+    }
+}
+
+/// `normal_map` sampling `map` instead of its placeholder.
+fn with_map(normal_map: NormalMap, map: Texture) -> NormalMap {
+    match normal_map {
+        NormalMap::None => NormalMap::None,
+        NormalMap::Normal { strength, .. } => NormalMap::Normal { map, strength },
+        NormalMap::Bump { scale, .. } => NormalMap::Bump { map, scale },
+    }
+}
+
+fn complex_ior(desc: IorDesc) -> std::result::Result<ComplexIor, String> {
+    match desc {
+        IorDesc::Preset(name) => match name.as_str() {
+            "gold" => Ok(GOLD),
+            "copper" => Ok(COPPER),
+            "aluminium" | "aluminum" => Ok(ALUMINIUM),
+            _ => Err(format!(
+                "unknown conductor {:?}, expected gold, copper or aluminium",
+                name
+            )),
+        },
+        IorDesc::Complex { eta, k } => {
+            if eta
+                .iter()
+                .chain(k.iter())
+                .any(|&c| !(0.0 <= c && c.is_finite()))
+            {
+                return Err(format!(
+                    "eta and k components must be non-negative, got {:?} and {:?}",
+                    eta, k
+                ));
+            }
+            Ok(ComplexIor {
+                eta: vec3(eta),
+                k: vec3(k),
+            })
+        }
+    }
+}
+
+/// The texture and, for image textures, the path of the image to load.
+fn texture(desc: TextureDesc) -> std::result::Result<(Texture, Option<PathBuf>), String> {
+    let texture = match desc {
+        TextureDesc::Solid { color } => Texture::solid(check_albedo(color)?),
+        TextureDesc::Checker { odd, even, scale } => Texture::Checker {
+            odd: check_albedo(odd)?,
+            even: check_albedo(even)?,
+            scale: check_scale(scale)?,
+        },
+        TextureDesc::Noise { color, scale } => Texture::Noise {
+            color: check_albedo(color)?,
+            scale: check_scale(scale)?,
+        },
+        TextureDesc::Turbulence {
+            color,
+            scale,
+            depth,
+        } => Texture::Turbulence {
+            color: check_albedo(color)?,
+            scale: check_scale(scale)?,
+            depth,
+        },
+        TextureDesc::Image { path } => return Ok((Texture::solid(Vec3::new()), Some(path))),
+    };
+    Ok((texture, None))
+}
+
+fn albedo_texture(desc: AlbedoDesc) -> std::result::Result<(Texture, Option<PathBuf>), String> {
+    match desc {
+        AlbedoDesc::Color(color) => Ok((Texture::solid(check_albedo(color)?), None)),
+        AlbedoDesc::Texture(desc) => texture(desc),
+    }
+}
+
+impl TryFrom<MaterialDesc> for Material {
+    type Error = String;
+
+    fn try_from(desc: MaterialDesc) -> std::result::Result<Material, String> {
+        match desc {
+            MaterialDesc::Lambertian(desc) => {
+                let (albedo, image) = albedo_texture(desc.albedo)?;
+                let (normal_map, normal_image) = normal_map(desc.normal_map)?;
+                Ok(Material {
+                    material: ObjectMaterial::Lambertian { albedo, normal_map },
+                    image,
+                    normal_image,
+                })
+            }
+            MaterialDesc::Principled(desc) => {
+                let (base_color, image) = albedo_texture(desc.base_color)?;
+                let (normal_map, normal_image) = normal_map(desc.normal_map)?;
+                Ok(Material {
+                    material: ObjectMaterial::Principled(Principled {
+                        base_color,
+                        metallic: check_unit("metallic", desc.metallic)?,
+                        roughness: check_unit("roughness", desc.roughness)?,
+                        specular: check_unit("specular", desc.specular)?,
+                        clearcoat: check_unit("clearcoat", desc.clearcoat)?,
+                        clearcoat_roughness: check_unit(
+                            "clearcoat_roughness",
+                            desc.clearcoat_roughness,
+                        )?,
+                        transmission: check_unit("transmission", desc.transmission)?,
+                        sheen: check_unit("sheen", desc.sheen)?,
+                        normal_map,
+                    }),
+                    image,
+                    normal_image,
+                })
+            }
+            MaterialDesc::Metal { albedo, fuzz } => {
+                if !(0.0 <= fuzz && fuzz <= 1.0) {
+                    return Err(format!("fuzz must be between 0 and 1, got {}", fuzz));
+                }
+                Ok(Material::from(ObjectMaterial::Metal {
+                    albedo: check_albedo(albedo)?,
+                    fuzz,
+                }))
+            }
+            MaterialDesc::Dielectric { ref_idx } => {
+                Ok(Material::from(ObjectMaterial::Dielectric {
+                    ref_idx: check_ref_idx(ref_idx)?,
+                }))
+            }
+            MaterialDesc::DispersiveDielectric { ior } => {
+                Ok(Material::from(ObjectMaterial::DispersiveDielectric {
+                    ior: dispersion(ior)?,
+                }))
+            }
+            MaterialDesc::Conductor { ior, roughness } => {
+                Ok(Material::from(ObjectMaterial::Conductor {
+                    ior: complex_ior(ior)?,
+                    roughness: check_roughness(roughness)?,
+                }))
+            }
+            MaterialDesc::RoughDielectric { ref_idx, roughness } => {
+                // Without a change of index, refraction is a delta the
+                // microfacet model cannot sample.
+                if (ref_idx - 1.0).abs() < 1e-3 {
+                    return Err(format!(
+                        "ref_idx of a rough dielectric must differ from 1, got {}",
+                        ref_idx
+                    ));
+                }
+                Ok(Material::from(ObjectMaterial::RoughDielectric {
+                    ref_idx: check_ref_idx(ref_idx)?,
+                    roughness: check_roughness(roughness)?,
+                }))
+            }
+            MaterialDesc::DiffuseLight { emit } => {
+                if emit.iter().any(|&c| !(0.0 <= c && c.is_finite())) {
+                    return Err(format!(
+                        "emit components must be non-negative, got {:?}",
+                        emit
+                    ));
+                }
+                Ok(Material::from(ObjectMaterial::DiffuseLight {
+                    emit: vec3(emit),
+                }))
+            }
+            MaterialDesc::Isotropic { albedo } => Ok(Material::from(ObjectMaterial::Isotropic {
+                albedo: check_albedo(albedo)?,
+            })),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ObjectDesc {
+    shape: Shape,
+    material: Material,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+fn deserialize_density<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<f32, D::Error> {
+    let density = f32::deserialize(deserializer)?;
+    if !(density > 0.0 && density.is_finite()) {
+        return Err(de::Error::custom(format!(
+            "density must be positive, got {}",
+            density
+        )));
+    }
+    Ok(density)
+}
+
+fn deserialize_albedo<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec3, D::Error> {
+    check_albedo(<[f32; 3]>::deserialize(deserializer)?).map_err(de::Error::custom)
+}
+
+/// Constant-density volume inside the closed surface `boundary`, scattering
+/// isotropically.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MediumDesc {
+    boundary: Shape,
+    #[serde(deserialize_with = "deserialize_density")]
+    density: f32,
+    #[serde(deserialize_with = "deserialize_albedo")]
+    albedo: Vec3,
+}
+
+/// Homogeneous fog filling the whole scene.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FogDesc {
+    #[serde(deserialize_with = "deserialize_density")]
+    density: f32,
+    #[serde(deserialize_with = "deserialize_albedo")]
+    albedo: Vec3,
+}
+
+/// Shapes or a model stored once and placed by any number of `[[instances]]`.
+/// Exactly one of `shape` and `mesh` is given; the materials of the model's
+/// MTL files are ignored in favor of the instance's.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PrototypeDesc {
+    name: String,
+    shape: Option<Shape>,
+    mesh: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+#[serde(untagged)]
+enum Scale {
+    Uniform(f32),
+    Axes([f32; 3]),
+}
+
+impl Default for Scale {
+    fn default() -> Scale {
+        Scale::Uniform(1.0)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InstanceDesc {
+    prototype: String,
+    #[serde(default)]
+    translate: [f32; 3],
+    /// Rotations about the x, y and z axes in degrees, applied in that order.
+    #[serde(default)]
+    rotate: [f32; 3],
+    #[serde(default)]
+    scale: Scale,
+    material: Material,
+}
+
+impl InstanceDesc {
+    /// Scales, then rotates, then translates.
+    fn transform(&self) -> std::result::Result<Transform, String> {
+        let scale = match self.scale {
+            Scale::Uniform(s) => [s, s, s],
+            Scale::Axes(s) => s,
+        };
+        let [rx, ry, rz] = self.rotate;
+        let m = Mat3x4::translation(vec3(self.translate))
+            * Mat3x4::rotation_z(rz.to_radians())
+            * Mat3x4::rotation_y(ry.to_radians())
+            * Mat3x4::rotation_x(rx.to_radians())
+            * Mat3x4::scaling(vec3(scale));
+        Transform::new(m).ok_or_else(|| format!("scale must be non-zero, got {:?}", scale))
+    }
+}
+
+fn default_intensity() -> f32 {
+    1.0
+}
+
+/// Falls back to `Background::default()`, the white-to-blue sky, when omitted.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum BackgroundDesc {
+    Constant {
+        color: [f32; 3],
+    },
+    Gradient {
+        bottom: [f32; 3],
+        top: [f32; 3],
+    },
+    /// An equirectangular `.hdr` or `.pfm` image, its path relative to the scene file.
+    Environment {
+        path: PathBuf,
+        /// Rotation about the y axis in degrees.
+        #[serde(default)]
+        rotation: f32,
+        #[serde(default = "default_intensity")]
+        intensity: f32,
+    },
+}
+
+fn check_color(name: &str, color: [f32; 3]) -> std::result::Result<Vec3, String> {
+    if color.iter().any(|&c| !(0.0 <= c && c.is_finite())) {
+        return Err(format!(
+            "{} components must be non-negative, got {:?}",
+            name, color
+        ));
+    }
+    Ok(vec3(color))
+}
+
+impl BackgroundDesc {
+    fn background(&self, dir: &Path) -> std::result::Result<Background, String> {
+        match self {
+            BackgroundDesc::Constant { color } => {
+                Ok(Background::Constant(check_color("color", *color)?))
+            }
+            BackgroundDesc::Gradient { bottom, top } => Ok(Background::Gradient {
+                bottom: check_color("bottom", *bottom)?,
+                top: check_color("top", *top)?,
+            }),
+            BackgroundDesc::Environment {
+                path,
+                rotation,
+                intensity,
+            } => {
+                if !(0.0 <= *intensity && intensity.is_finite()) {
+                    return Err(format!(
+                        "intensity must be non-negative, got {}",
+                        intensity
+                    ));
+                }
+                let map = HdrImage::load(dir.join(path)).map_err(|e| e.to_string())?;
+                Ok(Background::Environment {
+                    map,
+                    rotation: rotation.to_radians(),
+                    intensity: *intensity,
+                })
+            }
+        }
+    }
+}
+
+/// A Wavefront OBJ model, its path relative to the scene file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshDesc {
+    path: PathBuf,
+    #[serde(default)]
+    translate: [f32; 3],
+    #[serde(default = "default_scale")]
+    scale: f32,
+    /// Replaces the materials of the model's MTL files.
+    material: Option<Material>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    camera: CameraDesc,
+    #[serde(default)]
+    render: RenderDesc,
+    #[serde(default)]
+    objects: Vec<ObjectDesc>,
+    #[serde(default)]
+    meshes: Vec<MeshDesc>,
+    #[serde(default)]
+    prototypes: Vec<PrototypeDesc>,
+    #[serde(default)]
+    instances: Vec<InstanceDesc>,
+    #[serde(default)]
+    media: Vec<MediumDesc>,
+    fog: Option<FogDesc>,
+    background: Option<BackgroundDesc>,
+}
+
+/// Loads the images of image textures and normal maps, each once, into the
+/// geometry's texel buffer.
+struct ImageTextures<'a> {
+    dir: &'a Path,
+    /// Keyed by path and whether the image holds linear data.
+    loaded: HashMap<(PathBuf, bool), Texture>,
+}
+
+impl<'a> ImageTextures<'a> {
+    fn load(&mut self, path: &Path, linear: bool, geometry: &mut Geometry) -> Result<Texture> {
+        let key = (self.dir.join(path), linear);
+        if let Some(&texture) = self.loaded.get(&key) {
+            return Ok(texture);
+        }
+        let image = if linear {
+            HdrImage::load_linear(&key.0)?
+        } else {
+            HdrImage::load(&key.0)?
+        };
+        let texture = geometry.add_image_texture(&image);
+        self.loaded.insert(key, texture);
+        Ok(texture)
+    }
+
+    fn material(&mut self, material: &Material, geometry: &mut Geometry) -> Result<ObjectMaterial> {
+        let mut res = material.material;
+        if let Some(path) = &material.image {
+            let texture = self.load(path, false, geometry)?;
+            res = match res {
+                ObjectMaterial::Lambertian { normal_map, .. } => ObjectMaterial::Lambertian {
+                    albedo: texture,
+                    normal_map,
+                },
+                ObjectMaterial::Principled(principled) => ObjectMaterial::Principled(Principled {
+                    base_color: texture,
+                    ..principled
+                }),
+                material => material,
+            };
+        }
+        if let Some(path) = &material.normal_image {
+            let map = self.load(path, true, geometry)?;
+            res = match res {
+                ObjectMaterial::Lambertian { albedo, normal_map } => ObjectMaterial::Lambertian {
+                    albedo,
+                    normal_map: with_map(normal_map, map),
+                },
+                ObjectMaterial::Principled(principled) => ObjectMaterial::Principled(Principled {
+                    normal_map: with_map(principled.normal_map, map),
+                    ..principled
+                }),
+                material => material,
+            };
+        }
+        Ok(res)
+    }
+}
+
+/// Line of the `index`-th line reading `header`, such as `[[instances]]`, or
+/// 0 when there is none because the entry is written inline.
+fn header_line(s: &str, header: &str, index: usize) -> usize {
+    s.lines()
+        .enumerate()
+        .filter(|(_, line)| {
+            let line = line.split('#').next().unwrap_or("");
+            line.split_whitespace().collect::<String>() == header
+        })
+        .nth(index)
+        .map_or(0, |(i, _)| i + 1)
+}
+
+pub struct SceneFile {
+    pub camera: CameraDesc,
+    pub render: RenderDesc,
+    pub geometry: Geometry,
+    pub background: Background,
+    pub fog: Fog,
+}
+
+impl SceneFile {
+    /// Parses the scene stored at `path`, loading the models it references.
+    /// Errors in the scene itself carry the line and column of the offending
+    /// value; errors found afterwards, such as a reference to an undefined
+    /// prototype, name the entry and the line of its header.
+    pub fn parse(s: &str, path: &Path) -> Result<SceneFile> {
+        let desc: SceneDesc = toml::from_str(s).map_err(|e| Error::Scene {
+            path: path.display().to_string(),
+            line: 0,
+            message: e.to_string(),
+        })?;
+        // `table` is the array of tables holding the entry when `index` is
+        // given, the entry itself otherwise.
+        let scene_error = |table: &str, index: Option<usize>, message: String| {
+            let (header, entry) = match index {
+                Some(i) => (format!("[[{}]]", table), format!("{}[{}]", table, i)),
+                None => (format!("[{}]", table), table.to_string()),
+            };
+            Error::Scene {
+                path: path.display().to_string(),
+                line: header_line(s, &header, index.unwrap_or(0)),
+                message: format!("{}: {}", entry, message),
+            }
+        };
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut geometry = Geometry::default();
+        let mut textures = ImageTextures {
+            dir,
+            loaded: HashMap::new(),
+        };
+        for (i, object) in desc.objects.into_iter().enumerate() {
+            let material = textures
+                .material(&object.material, &mut geometry)
+                .map_err(|e| scene_error("objects", Some(i), e.to_string()))?;
+            for shape in object.shape.0 {
+                geometry.objects.push(Object { shape, material });
+            }
+        }
+        for (i, mesh) in desc.meshes.into_iter().enumerate() {
+            let mesh_error = |e: Error| scene_error("meshes", Some(i), e.to_string());
+            let placement = Placement {
+                translation: vec3(mesh.translate),
+                scale: mesh.scale,
+            };
+            let model = load_obj(dir.join(&mesh.path), placement).map_err(mesh_error)?;
+            let material = match &mesh.material {
+                Some(material) => Some(
+                    textures
+                        .material(material, &mut geometry)
+                        .map_err(mesh_error)?,
+                ),
+                None => None,
+            };
+            for (m, mtl_material) in &model.meshes {
+                geometry.add_mesh(m, material.unwrap_or(*mtl_material));
+            }
+        }
+        let mut prototypes = HashMap::new();
+        for (i, prototype) in desc.prototypes.into_iter().enumerate() {
+            let prototype_error = |message: String| scene_error("prototypes", Some(i), message);
+            let shapes = match (prototype.shape, &prototype.mesh) {
+                (Some(shape), None) => {
+                    if shape.0.iter().any(|shape| match shape {
+                        ObjectShape::Plane { .. } => true,
+                        _ => false,
+                    }) {
+                        return Err(prototype_error(format!(
+                            "prototype {:?}: planes cannot be instanced",
+                            prototype.name
+                        )));
+                    }
+                    shape.0
+                }
+                (None, Some(mesh)) => {
+                    let model = load_obj(dir.join(mesh), Placement::default())
+                        .map_err(|e| prototype_error(e.to_string()))?;
+                    let mut shapes = vec![];
+                    for (m, _) in &model.meshes {
+                        shapes.extend(geometry.append_mesh(m));
+                    }
+                    shapes
+                }
+                _ => {
+                    return Err(prototype_error(format!(
+                        "prototype {:?} needs exactly one of shape and mesh",
+                        prototype.name
+                    )))
+                }
+            };
+            let handle = geometry.add_prototype(shapes);
+            if prototypes.insert(prototype.name.clone(), handle).is_some() {
+                return Err(prototype_error(format!(
+                    "prototype {:?} is defined twice",
+                    prototype.name
+                )));
+            }
+        }
+        for (i, instance) in desc.instances.into_iter().enumerate() {
+            let instance_error = |message: String| scene_error("instances", Some(i), message);
+            let prototype = *prototypes.get(&instance.prototype).ok_or_else(|| {
+                instance_error(format!("unknown prototype {:?}", instance.prototype))
+            })?;
+            let transform = instance.transform().map_err(instance_error)?;
+            let material = textures
+                .material(&instance.material, &mut geometry)
+                .map_err(|e| instance_error(e.to_string()))?;
+            geometry.add_instance(prototype, transform, material);
+        }
+        for (i, medium) in desc.media.into_iter().enumerate() {
+            if medium.boundary.0.iter().any(|shape| match shape {
+                ObjectShape::Plane { .. } => true,
+                _ => false,
+            }) {
+                return Err(scene_error(
+                    "media",
+                    Some(i),
+                    "a medium cannot be bounded by a plane".to_string(),
+                ));
+            }
+            geometry.add_medium(
+                medium.boundary.0,
+                medium.density,
+                ObjectMaterial::Isotropic {
+                    albedo: medium.albedo,
+                },
+            );
+        }
+        let fog = desc.fog.map_or(Fog::none(), |fog| Fog {
+            density: fog.density,
+            albedo: fog.albedo,
+        });
+        let background = match &desc.background {
+            Some(background) => background
+                .background(dir)
+                .map_err(|message| scene_error("background", None, message))?,
+            None => Background::default(),
+        };
+        Ok(SceneFile {
+            camera: desc.camera,
+            render: desc.render,
+            geometry,
+            background,
+            fog,
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SceneFile> {
+        let path = path.as_ref();
+        let s = fs::read_to_string(path).map_err(|e| Error::Scene {
+            path: path.display().to_string(),
+            line: 0,
+            message: e.to_string(),
+        })?;
+        SceneFile::parse(&s, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAMERA: &str = "
+[camera]
+lookfrom = [0.0, 0.0, 1.0]
+lookat = [0.0, 0.0, 0.0]
+vfov = 40.0
+";
+
+    fn parse(body: &str) -> Result<SceneFile> {
+        SceneFile::parse(&format!("{}{}", CAMERA, body), Path::new("test.toml"))
+    }
+
+    fn error(body: &str) -> String {
+        parse(body)
+            .err()
+            .expect("the scene should not parse")
+            .to_string()
+    }
+
+    #[test]
+    fn valid_scene_is_parsed() {
+        let scene = parse(
+            "
+[render]
+width = 64
+ray_per_pixel = 4
+
+[[objects]]
+shape = { type = \"sphere\", center = [0.0, 0.0, -1.0], radius = 0.5 }
+material = { type = \"lambertian\", albedo = [0.1, 0.2, 0.5] }
+
+[[objects]]
+shape = { type = \"sphere\", center = [1.0, 0.0, -1.0], radius = 0.5 }
+material = { type = \"metal\", albedo = [0.8, 0.6, 0.2], fuzz = 0.0 }
+",
+        )
+        .unwrap();
+        assert_eq!(scene.camera.vfov, 40.0);
+        assert_eq!(scene.render.width, Some(64));
+        assert_eq!(scene.render.height, None);
+        assert_eq!(scene.geometry.objects.len(), 2);
+    }
+
+    #[test]
+    fn unknown_field_is_rejected() {
+        let message = error(
+            "
+[[objects]]
+shape = { type = \"sphere\", center = [0.0, 0.0, -1.0], radius = 0.5 }
+material = { type = \"metal\", albedo = [0.8, 0.6, 0.2], fuz = 0.0 }
+",
+        );
+        assert!(message.starts_with("test.toml: "), "{}", message);
+        assert!(message.contains("unknown field `fuz`"), "{}", message);
+    }
+
+    #[test]
+    fn out_of_range_value_is_rejected() {
+        let message = error(
+            "
+[[objects]]
+shape = { type = \"sphere\", center = [0.0, 0.0, -1.0], radius = 0.5 }
+material = { type = \"metal\", albedo = [0.8, 0.6, 0.2], fuzz = 2.0 }
+",
+        );
+        assert!(
+            message.contains("fuzz must be between 0 and 1, got 2"),
+            "{}",
+            message
+        );
+        assert!(message.starts_with("test.toml: "), "{}", message);
+    }
+
+    #[test]
+    fn rough_dielectric_without_index_change_is_rejected() {
+        let message = error(
+            "
+[[objects]]
+shape = { type = \"sphere\", center = [0.0, 0.0, -1.0], radius = 0.5 }
+material = { type = \"rough_dielectric\", ref_idx = 1.0, roughness = 0.5 }
+",
+        );
+        assert!(
+            message.contains("ref_idx of a rough dielectric must differ from 1, got 1"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn unknown_prototype_is_reported_at_its_instance() {
+        let message = error(
+            "
+[[prototypes]]
+name = \"pillar\"
+shape = { type = \"box\", min = [-0.1, 0.0, -0.1], max = [0.1, 1.0, 0.1] }
+
+[[instances]]
+prototype = \"pillar\"
+material = { type = \"lambertian\", albedo = [0.5, 0.5, 0.5] }
+
+[[instances]]
+prototype = \"pilar\"
+material = { type = \"lambertian\", albedo = [0.5, 0.5, 0.5] }
+",
+        );
+        assert_eq!(
+            message,
+            "test.toml:15: instances[1]: unknown prototype \"pilar\""
+        );
+    }
+
+    #[test]
+    fn unreadable_model_is_reported_at_its_mesh() {
+        let message = error(
+            "
+[[meshes]]
+path = \"missing.obj\"
+",
+        );
+        assert!(
+            message.starts_with("test.toml:7: meshes[0]: missing.obj: "),
+            "{}",
+            message
+        );
+    }
+}
@@ -0,0 +1,173 @@
+#![feature(abi_ptx)]
+
+use cuda_tools::cuda_slice::CUDASlice;
+use rayon::prelude::*;
+use std::cell::Cell;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+#[derive(Clone, Copy, Default)]
+struct Dim3 {
+    x: i32,
+    y: i32,
+    z: i32,
+}
+
+#[derive(Clone, Copy, Default)]
+struct ThreadContext {
+    thread_idx: Dim3,
+    block_idx: Dim3,
+    block_dim: Dim3,
+    grid_dim: Dim3,
+}
+
+thread_local! {
+    static CONTEXT: Cell<ThreadContext> = Cell::new(ThreadContext::default());
+}
+
+#[inline(always)]
+fn context() -> ThreadContext {
+    CONTEXT.with(|context| context.get())
+}
+
+pub unsafe fn _thread_idx_x() -> i32 {
+    context().thread_idx.x
+}
+
+pub unsafe fn _thread_idx_y() -> i32 {
+    context().thread_idx.y
+}
+
+pub unsafe fn _thread_idx_z() -> i32 {
+    context().thread_idx.z
+}
+
+pub unsafe fn _block_idx_x() -> i32 {
+    context().block_idx.x
+}
+
+pub unsafe fn _block_idx_y() -> i32 {
+    context().block_idx.y
+}
+
+pub unsafe fn _block_idx_z() -> i32 {
+    context().block_idx.z
+}
+
+pub unsafe fn _block_dim_x() -> i32 {
+    context().block_dim.x
+}
+
+pub unsafe fn _block_dim_y() -> i32 {
+    context().block_dim.y
+}
+
+pub unsafe fn _block_dim_z() -> i32 {
+    context().block_dim.z
+}
+
+pub unsafe fn _grid_dim_x() -> i32 {
+    context().grid_dim.x
+}
+
+pub unsafe fn _grid_dim_y() -> i32 {
+    context().grid_dim.y
+}
+
+pub unsafe fn _grid_dim_z() -> i32 {
+    context().grid_dim.z
+}
+
+/// Types `atomic_cxchg` can operate on.
+pub trait AtomicCxchg: Copy {
+    unsafe fn cxchg(dst: *mut Self, old: Self, src: Self) -> (Self, bool);
+}
+
+impl AtomicCxchg for u32 {
+    unsafe fn cxchg(dst: *mut u32, old: u32, src: u32) -> (u32, bool) {
+        let dst = &*(dst as *const AtomicU32);
+        match dst.compare_exchange(old, src, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(res) => (res, true),
+            Err(res) => (res, false),
+        }
+    }
+}
+
+impl AtomicCxchg for u64 {
+    unsafe fn cxchg(dst: *mut u64, old: u64, src: u64) -> (u64, bool) {
+        let dst = &*(dst as *const AtomicU64);
+        match dst.compare_exchange(old, src, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(res) => (res, true),
+            Err(res) => (res, false),
+        }
+    }
+}
+
+/// Same contract as `core::intrinsics::atomic_cxchg`: stores `src` into `*dst`
+/// if it equals `old` and returns the previous value and whether the store happened.
+pub unsafe fn atomic_cxchg<T: AtomicCxchg>(dst: *mut T, old: T, src: T) -> (T, bool) {
+    T::cxchg(dst, old, src)
+}
+
+/// Wraps host memory in a `CUDASlice` so that kernel arguments can point at it.
+///
+/// The slice must outlive every launch that reads it. Elements written by a
+/// kernel have to be `UnsafeCell`s, exactly as on the device.
+pub unsafe fn host_slice<'a, T>(xs: &'a [T]) -> CUDASlice<'a, T> {
+    CUDASlice::from_raw_parts(xs.as_ptr() as *mut T, xs.len())
+}
+
+struct Shared<'a, A>(&'a A);
+
+unsafe impl<'a, A> Sync for Shared<'a, A> {}
+
+/// Runs `kernel` once per thread of the given grid, with the index intrinsics
+/// of this crate returning that thread's coordinates.
+///
+/// Takes the same arguments as `cuda_tools::runtime::Runtime::launch`. Blocks
+/// are distributed over a thread pool and the threads of a block run one after
+/// another, so there is no barrier between them.
+pub unsafe fn launch<A>(
+    kernel: extern "ptx-kernel" fn(&A),
+    args: &A,
+    grid_dim_x: usize,
+    grid_dim_y: usize,
+    grid_dim_z: usize,
+    block_dim_x: usize,
+    block_dim_y: usize,
+    block_dim_z: usize,
+) {
+    let grid_dim = Dim3 {
+        x: grid_dim_x as i32,
+        y: grid_dim_y as i32,
+        z: grid_dim_z as i32,
+    };
+    let block_dim = Dim3 {
+        x: block_dim_x as i32,
+        y: block_dim_y as i32,
+        z: block_dim_z as i32,
+    };
+    let n_block = grid_dim_x * grid_dim_y * grid_dim_z;
+    let args = Shared(args);
+    (0..n_block).into_par_iter().for_each(|b| {
+        let block_idx = Dim3 {
+            x: (b % grid_dim_x) as i32,
+            y: (b / grid_dim_x % grid_dim_y) as i32,
+            z: (b / grid_dim_x / grid_dim_y) as i32,
+        };
+        for z in 0..block_dim.z {
+            for y in 0..block_dim.y {
+                for x in 0..block_dim.x {
+                    CONTEXT.with(|context| {
+                        context.set(ThreadContext {
+                            thread_idx: Dim3 { x, y, z },
+                            block_idx,
+                            block_dim,
+                            grid_dim,
+                        })
+                    });
+                    kernel(args.0);
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,317 @@
+//! The example kernels, unchanged, launched on the emulator.
+
+#![feature(abi_ptx)]
+
+use core::cell::UnsafeCell;
+use cuda_tools::cuda_slice::CUDASlice;
+use ray_tracing_kernel as kernel;
+use simt_emulator::*;
+
+use kernel::background::*;
+use kernel::camera::*;
+use kernel::medium::*;
+use kernel::normal_map::*;
+use kernel::object::*;
+use kernel::path::*;
+use kernel::ray_trace_args::*;
+use kernel::texture::*;
+use kernel::vec3::*;
+
+fn cells<T: Copy>(value: T, n: usize) -> Vec<UnsafeCell<T>> {
+    (0..n).map(|_| UnsafeCell::new(value)).collect()
+}
+
+#[test]
+fn vector_add_covers_a_partial_last_block() {
+    let n = 1000;
+    let m = 256;
+    let xs: Vec<f32> = (0..n).map(|i| i as f32).collect();
+    let ys: Vec<f32> = (0..n).map(|i| 2.0 * i as f32).collect();
+    let zs = cells(-1.0f32, n);
+    let args = unsafe {
+        vector_add_kernel::Arguments {
+            xs: host_slice(&xs),
+            ys: host_slice(&ys),
+            zs: host_slice(&zs),
+        }
+    };
+    unsafe {
+        launch(
+            vector_add_kernel::vector_add,
+            &args,
+            (n + m - 1) / m,
+            1,
+            1,
+            m,
+            1,
+            1,
+        );
+    }
+    for (i, z) in zs.into_iter().enumerate() {
+        assert_eq!(z.into_inner(), xs[i] + ys[i], "element {}", i);
+    }
+}
+
+struct Counters<'a> {
+    count32: CUDASlice<'a, UnsafeCell<u32>>,
+    count64: CUDASlice<'a, UnsafeCell<u64>>,
+}
+
+/// Adds one to both counters with compare-and-swap loops.
+extern "ptx-kernel" fn increment(args: &Counters) {
+    unsafe {
+        let p = args.count32[0].get();
+        let mut old = 0;
+        loop {
+            let (prev, ok) = atomic_cxchg(p, old, old + 1);
+            if ok {
+                break;
+            }
+            old = prev;
+        }
+        let p = args.count64[0].get();
+        let mut old = 0;
+        loop {
+            let (prev, ok) = atomic_cxchg(p, old, old + 1);
+            if ok {
+                break;
+            }
+            old = prev;
+        }
+    }
+}
+
+#[test]
+fn contended_atomic_cxchg_loses_no_update() {
+    let count32 = cells(0u32, 1);
+    let count64 = cells(0u64, 1);
+    let args = unsafe {
+        Counters {
+            count32: host_slice(&count32),
+            count64: host_slice(&count64),
+        }
+    };
+    let (n_block, m) = (64, 256);
+    unsafe {
+        launch(increment, &args, n_block, 1, 1, m, 1, 1);
+    }
+    let n = (n_block * m) as u64;
+    assert_eq!(count32.into_iter().next().unwrap().into_inner() as u64, n);
+    assert_eq!(count64.into_iter().next().unwrap().into_inner(), n);
+}
+
+const H: usize = 5;
+const W: usize = 7;
+const RAY_PER_PIXEL: usize = 3;
+
+fn lambertian(color: Vec3) -> ObjectMaterial {
+    ObjectMaterial::Lambertian {
+        albedo: Texture::solid(color),
+        normal_map: NormalMap::None,
+    }
+}
+
+fn objects() -> Vec<Object> {
+    vec![
+        Object {
+            shape: ObjectShape::Sphere {
+                center: Vec3 {
+                    x: 0.0,
+                    y: 0.0,
+                    z: -1.0,
+                },
+                radius: 0.5,
+            },
+            material: lambertian(Vec3 {
+                x: 0.1,
+                y: 0.2,
+                z: 0.5,
+            }),
+        },
+        Object {
+            shape: ObjectShape::Sphere {
+                center: Vec3 {
+                    x: 0.0,
+                    y: -100.5,
+                    z: -1.0,
+                },
+                radius: 100.0,
+            },
+            material: lambertian(Vec3 {
+                x: 0.8,
+                y: 0.8,
+                z: 0.0,
+            }),
+        },
+    ]
+}
+
+fn camera() -> Camera {
+    Camera {
+        origin: Vec3::new(),
+        lower_left_corner: Vec3 {
+            x: -2.0,
+            y: -1.0,
+            z: -1.0,
+        },
+        horizontal: Vec3 {
+            x: 4.0,
+            y: 0.0,
+            z: 0.0,
+        },
+        vertical: Vec3 {
+            x: 0.0,
+            y: 2.0,
+            z: 0.0,
+        },
+        u: Vec3 {
+            x: 1.0,
+            y: 0.0,
+            z: 0.0,
+        },
+        v: Vec3 {
+            x: 0.0,
+            y: 1.0,
+            z: 0.0,
+        },
+        w: Vec3 {
+            x: 0.0,
+            y: 0.0,
+            z: 1.0,
+        },
+        lens_radius: 0.0,
+        time0: 0.0,
+        time1: 0.0,
+    }
+}
+
+/// Arguments tracing `objects` without a BVH into `image`.
+unsafe fn ray_trace_args<'a>(
+    objects: &'a [Object],
+    image: &'a [UnsafeCell<Vec3>],
+) -> RayTraceArgs<'a> {
+    RayTraceArgs {
+        image_len: image.len(),
+        image: host_slice(image),
+        h: H,
+        w: W,
+        objects_len: objects.len(),
+        objects: host_slice(objects),
+        bvh_len: 0,
+        bvh: host_slice(&[]),
+        bounded_len: 0,
+        vertices_len: 0,
+        vertices: host_slice(&[]),
+        normals_len: 0,
+        normals: host_slice(&[]),
+        texcoords_len: 0,
+        texcoords: host_slice(&[]),
+        indices_len: 0,
+        indices: host_slice(&[]),
+        prototypes_len: 0,
+        prototypes: host_slice(&[]),
+        prototype_bvh_len: 0,
+        prototype_bvh: host_slice(&[]),
+        transforms_len: 0,
+        transforms: host_slice(&[]),
+        media_len: 0,
+        media: host_slice(&[]),
+        fog: Fog::none(),
+        lights_len: 0,
+        lights: host_slice(&[]),
+        texels_len: 0,
+        texels: host_slice(&[]),
+        background: Background::Gradient {
+            bottom: Vec3 {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+            },
+            top: Vec3 {
+                x: 0.5,
+                y: 0.7,
+                z: 1.0,
+            },
+        },
+        environment_len: 0,
+        environment: host_slice(&[]),
+        ray_per_pixel: RAY_PER_PIXEL,
+        camera: camera(),
+        path: PathSettings {
+            max_depth: 8,
+            ..PathSettings::default()
+        },
+    }
+}
+
+/// Sums of the samples of every pixel, traced on this thread.
+fn expected(args: &RayTraceArgs) -> Vec<Vec3> {
+    let scene = args.scene();
+    let mut image = vec![Vec3::new(); H * W];
+    for i in 0..H * W * RAY_PER_PIXEL {
+        let (p, res) = kernel::kernel::sample(&scene, H, W, RAY_PER_PIXEL, i);
+        image[p] += res;
+    }
+    image
+}
+
+fn assert_close(image: Vec<UnsafeCell<Vec3>>, expected: &[Vec3], tolerance: f32) {
+    for (i, (a, e)) in image.into_iter().zip(expected).enumerate() {
+        let a = a.into_inner();
+        for &(a, e) in &[(a.x, e.x), (a.y, e.y), (a.z, e.z)] {
+            assert!(
+                (a - e).abs() <= tolerance * e.abs().max(1.0),
+                "pixel {}: {} != {}",
+                i,
+                a,
+                e
+            );
+        }
+    }
+}
+
+#[test]
+fn ray_trace_accumulates_every_sample() {
+    let objects = objects();
+    let image = cells(Vec3::new(), H * W);
+    let args = unsafe { ray_trace_args(&objects, &image) };
+    let expected = expected(&args);
+    // 105 samples do not fill the last of two 64-thread blocks.
+    let (n, m) = (H * W * RAY_PER_PIXEL, 64);
+    unsafe {
+        launch(
+            kernel::kernel::ray_trace,
+            &args,
+            (n + m - 1) / m,
+            1,
+            1,
+            m,
+            1,
+            1,
+        );
+    }
+    // The atomic adds sum the samples of a pixel in any order.
+    assert_close(image, &expected, 1e-5);
+}
+
+#[test]
+fn ray_trace_pixel_sums_every_sample() {
+    let objects = objects();
+    let image = cells(Vec3::new(), H * W);
+    let args = unsafe { ray_trace_args(&objects, &image) };
+    let expected = expected(&args);
+    let (n, m) = (H * W, 16);
+    unsafe {
+        launch(
+            kernel::kernel::ray_trace_pixel,
+            &args,
+            (n + m - 1) / m,
+            1,
+            1,
+            m,
+            1,
+            1,
+        );
+    }
+    assert_close(image, &expected, 0.0);
+}
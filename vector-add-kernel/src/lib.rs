@@ -5,6 +5,8 @@
 #[macro_use]
 extern crate cuda_tools;
 
+pub mod arch;
+
 use cuda_tools::cuda_slice::CUDASlice;
 use core::cell::UnsafeCell;
 
@@ -15,14 +17,9 @@ pub struct Arguments<'a> {
 }
 
 #[no_mangle]
-#[cfg(not(target_arch = "nvptx64"))]
-pub extern "ptx-kernel" fn vector_add(args: &Arguments) {}
-
-#[no_mangle]
-#[cfg(target_arch = "nvptx64")]
 pub extern "ptx-kernel" fn vector_add(args: &Arguments) {
     let i = unsafe {
-        use core::arch::nvptx::*;
+        use crate::arch::*;
         _block_dim_x() * _block_idx_x() + _thread_idx_x()
     } as usize;
     unsafe {
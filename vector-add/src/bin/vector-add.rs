@@ -1,3 +1,6 @@
 fn main() {
-    vector_add::run();
+    if let Err(e) = vector_add::run() {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,40 @@
+use device_runtime::RuntimeError;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    DeviceInit(RuntimeError),
+    Alloc(RuntimeError),
+    Launch(RuntimeError),
+    Readback(RuntimeError),
+    Mismatch { index: usize, expected: f32, actual: f32 },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::DeviceInit(e) => write!(f, "failed to initialize the device: {}", e),
+            Error::Alloc(e) => write!(f, "failed to allocate device memory: {}", e),
+            Error::Launch(e) => write!(f, "failed to launch the kernel: {}", e),
+            Error::Readback(e) => write!(f, "failed to copy the result to the host: {}", e),
+            Error::Mismatch {
+                index,
+                expected,
+                actual,
+            } => write!(f, "zs[{}] is {}, expected {}", index, actual, expected),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::DeviceInit(e) | Error::Alloc(e) | Error::Launch(e) | Error::Readback(e) => {
+                Some(e)
+            }
+            _ => None,
+        }
+    }
+}
@@ -3,55 +3,101 @@
 #[macro_use]
 extern crate cuda_tools;
 
+pub mod error;
+
 use core::cell::UnsafeCell;
+use error::*;
 use rand::prelude::*;
+use device_runtime::{DeviceRuntime, RuntimeError};
 
 const KERNEL: &str = include_kernel!();
 const N: usize = 1 << 24;
 
-pub fn run() {
-    let mut runtime = cuda_tools::runtime::Runtime::new(0, KERNEL).unwrap();
+pub fn vector_add<R: DeviceRuntime>(runtime: &mut R, xs: &[f32], ys: &[f32]) -> Result<Vec<f32>> {
     runtime.record_function_name(vector_add_kernel::vector_add, "vector_add");
 
-    let mut rng = rand::thread_rng();
-    let mut xs = vec![];
-    for _ in 0..N {
-        xs.push(rng.gen());
-    }
-    let mut ys = vec![];
-    for _ in 0..N {
-        ys.push(rng.gen());
-    }
+    let n = xs.len();
     let mut zs = vec![];
-    for i in 0..N {
+    for _ in 0..n {
         zs.push(UnsafeCell::new(0.0));
     }
-    let xs_d = runtime.alloc_slice(&xs).unwrap();
-    let ys_d = runtime.alloc_slice(&ys).unwrap();
-    let zs_d = runtime.alloc_slice(&zs).unwrap();
+    let xs_d = runtime.alloc_slice(xs).map_err(Error::Alloc)?;
+    let ys_d = runtime.alloc_slice(ys).map_err(Error::Alloc)?;
+    let zs_d = runtime.alloc_slice(&zs).map_err(Error::Alloc)?;
     let args = vector_add_kernel::Arguments {
         xs: xs_d,
         ys: ys_d,
         zs: zs_d,
     };
 
+    let m = 256;
     runtime
         .launch(
             vector_add_kernel::vector_add,
             &args,
-            N / 256,
+            (n + m - 1) / m,
             1,
             1,
-            256,
+            m,
             1,
             1,
         )
-        .unwrap();
+        .map_err(Error::Launch)?;
+
+    let zs = runtime.to_host(&args.zs).map_err(Error::Readback)?;
+    Ok(zs.into_iter().map(|x| x.into_inner()).collect())
+}
+
+pub fn run() -> Result<()> {
+    let mut runtime = cuda_tools::runtime::Runtime::new(0, KERNEL)
+        .map_err(|e| Error::DeviceInit(RuntimeError(format!("{:?}", e))))?;
+
+    let mut rng = rand::thread_rng();
+    let mut xs: Vec<f32> = vec![];
+    for _ in 0..N {
+        xs.push(rng.gen());
+    }
+    let mut ys: Vec<f32> = vec![];
+    for _ in 0..N {
+        ys.push(rng.gen());
+    }
 
-    let zs = args.zs.to_host().unwrap();
-    let zs: Vec<f32> = zs.into_iter().map(|x| x.into_inner()).collect();
+    let zs = vector_add(&mut runtime, &xs, &ys)?;
     for i in 0..N {
-        assert!((zs[i] - (xs[i] + ys[i])).abs() < 1e-5);
+        if !((zs[i] - (xs[i] + ys[i])).abs() < 1e-5) {
+            return Err(Error::Mismatch {
+                index: i,
+                expected: xs[i] + ys[i],
+                actual: zs[i],
+            });
+        }
     }
     println!("ok");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use device_runtime::HostRuntime;
+
+    #[test]
+    fn host_runtime_adds_every_element() {
+        // Not a multiple of the block size, so the last block is partly idle.
+        let n = 1000;
+        let xs: Vec<f32> = (0..n).map(|i| i as f32).collect();
+        let ys: Vec<f32> = (0..n).map(|i| 0.5 * i as f32).collect();
+        let mut runtime = HostRuntime::new();
+        let zs = vector_add(&mut runtime, &xs, &ys).unwrap();
+        assert_eq!(zs.len(), n);
+        for i in 0..n {
+            assert_eq!(zs[i], xs[i] + ys[i], "element {}", i);
+        }
+    }
+
+    #[test]
+    fn host_runtime_handles_empty_input() {
+        let mut runtime = HostRuntime::new();
+        assert!(vector_add(&mut runtime, &[], &[]).unwrap().is_empty());
+    }
 }